@@ -0,0 +1,169 @@
+use std::str::FromStr;
+
+use ore::{BUS_ADDRESSES, MINT_ADDRESS, TREASURY_ADDRESS};
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+use solana_program::{
+    address_lookup_table::{instruction, state::AddressLookupTable, AddressLookupTableAccount},
+    hash::Hash,
+    instruction::Instruction,
+    message::{v0, Message, VersionedMessage},
+    pubkey::Pubkey,
+    system_instruction, sysvar,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, signature::Signer};
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::{
+    rpc_pool::RpcPool,
+    utils::{proof_pubkey, treasury_tokens_pubkey},
+    AltArgs, AltCommands, Miner, WALLETS,
+};
+
+// Addresses added per extend transaction, so each one fits in a packet
+const EXTEND_CHUNK_SIZE: usize = 20;
+
+pub async fn fetch_lookup_table(rpc_pool: &RpcPool, address: Pubkey) -> ClientResult<AddressLookupTableAccount> {
+    let data = rpc_pool
+        .read(|client| async move { client.get_account_data(&address).await })
+        .await?;
+    let table = AddressLookupTable::deserialize(&data).map_err(|err| ClientError {
+        request: None,
+        kind: ClientErrorKind::Custom(format!("Failed to parse lookup table: {}", err)),
+    })?;
+    Ok(AddressLookupTableAccount {
+        key: address,
+        addresses: table.addresses.to_vec(),
+    })
+}
+
+impl Miner {
+    pub async fn alt(&self, args: AltArgs) {
+        let signer = self.signer();
+        match args.command {
+            AltCommands::Create => {
+                let recent_slot = self
+                    .rpc_pool
+                    .read(|client| async move { client.get_slot_with_commitment(CommitmentConfig::finalized()).await })
+                    .await;
+                let recent_slot = match recent_slot {
+                    Ok(slot) => slot,
+                    Err(err) => {
+                        println!("Failed to fetch slot: {:?}", err);
+                        return;
+                    }
+                };
+                let (ix, address) = instruction::create_lookup_table(signer.pubkey(), signer.pubkey(), recent_slot);
+                println!("Creating lookup table {}...", address);
+                if let Err(err) = self.send_and_confirm(&[ix], false, false, vec![&signer]).await {
+                    println!("Failed to create lookup table: {:?}", err);
+                    return;
+                }
+                self.extend_lookup_table(address, Vec::new()).await;
+                println!("Created lookup table {}, use it with --lookup-table {}", address, address);
+            }
+            AltCommands::Extend(args) => {
+                let address = match args.address {
+                    Some(address) => Pubkey::from_str(&address).expect("Failed to parse lookup table address"),
+                    None => match self.lookup_tables.first() {
+                        Some(table) => table.key,
+                        None => {
+                            println!("No lookup table given, pass its address or --lookup-table");
+                            return;
+                        }
+                    },
+                };
+                let existing = match fetch_lookup_table(&self.rpc_pool, address).await {
+                    Ok(table) => table.addresses,
+                    Err(err) => {
+                        println!("Failed to fetch lookup table {}: {:?}", address, err);
+                        return;
+                    }
+                };
+                self.extend_lookup_table(address, existing).await;
+            }
+        }
+    }
+
+    // Adds every fleet address not already in the table
+    async fn extend_lookup_table(&self, address: Pubkey, existing: Vec<Pubkey>) {
+        let signer = self.signer();
+        let missing: Vec<Pubkey> = self
+            .lookup_table_addresses()
+            .into_iter()
+            .filter(|pubkey| !existing.contains(pubkey))
+            .collect();
+        if missing.is_empty() {
+            println!("Lookup table {} already has every address", address);
+            return;
+        }
+        for chunk in missing.chunks(EXTEND_CHUNK_SIZE) {
+            let ix = instruction::extend_lookup_table(address, signer.pubkey(), Some(signer.pubkey()), chunk.to_vec());
+            match self.send_and_confirm(&[ix], false, false, vec![&signer]).await {
+                Ok(_sig) => println!("Added {} addresses to lookup table {}", chunk.len(), address),
+                Err(err) => {
+                    println!("Failed to extend lookup table: {:?}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    // The ore program accounts plus the proof and token account of each wallet with a
+    // keypair. Signers and invoked programs can't be loaded from a lookup table, so they
+    // are left out.
+    fn lookup_table_addresses(&self) -> Vec<Pubkey> {
+        let mut addresses = vec![
+            TREASURY_ADDRESS,
+            treasury_tokens_pubkey(),
+            MINT_ADDRESS,
+            sysvar::slot_hashes::ID,
+            spl_token::id(),
+        ];
+        addresses.extend(BUS_ADDRESSES);
+        for signer in (1..WALLETS + 1).filter_map(|wallet| self.configured_signer(wallet)) {
+            let authority = signer.pubkey();
+            addresses.push(proof_pubkey(authority));
+            addresses.push(get_associated_token_address(&authority, &MINT_ADDRESS));
+        }
+        addresses
+    }
+
+    pub fn compile_message(
+        &self,
+        ixs: &[Instruction],
+        payer: &Pubkey,
+        nonce_pubkey: Option<&Pubkey>,
+        blockhash: Hash,
+    ) -> VersionedMessage {
+        compile_message(ixs, payer, nonce_pubkey, blockhash, &self.lookup_tables)
+    }
+}
+
+// Compiles a v0 message resolving accounts through the lookup tables when any are
+// configured, otherwise a legacy message. Transactions signed against a durable nonce
+// advance it first.
+pub fn compile_message(
+    ixs: &[Instruction],
+    payer: &Pubkey,
+    nonce_pubkey: Option<&Pubkey>,
+    blockhash: Hash,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> VersionedMessage {
+    if !lookup_tables.is_empty() {
+        let mut all_ixs: Vec<Instruction> = nonce_pubkey
+            .map(|nonce_pubkey| system_instruction::advance_nonce_account(nonce_pubkey, payer))
+            .into_iter()
+            .collect();
+        all_ixs.extend_from_slice(ixs);
+        match v0::Message::try_compile(payer, &all_ixs, lookup_tables, blockhash) {
+            Ok(message) => return VersionedMessage::V0(message),
+            Err(err) => println!("Failed to compile v0 message, using a legacy one: {:?}", err),
+        }
+    }
+    let mut message = match nonce_pubkey {
+        Some(nonce_pubkey) => Message::new_with_nonce(ixs.to_vec(), Some(payer), nonce_pubkey, payer),
+        None => Message::new(ixs, Some(payer)),
+    };
+    message.recent_blockhash = blockhash;
+    VersionedMessage::Legacy(message)
+}
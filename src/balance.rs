@@ -11,12 +11,15 @@ impl Miner {
 
     pub async fn balance(&self, signer: &Keypair) {
         let address = signer.pubkey();
-        let client = self.rpc_client.clone();
         let token_account_address = spl_associated_token_account::get_associated_token_address(
             &address,
             &ore::MINT_ADDRESS,
         );
-        match client.get_token_account(&token_account_address).await {
+        let token_account = self
+            .rpc_pool
+            .read(|client| async move { client.get_token_account(&token_account_address).await })
+            .await;
+        match token_account {
             Ok(token_account) => {
                 if let Some(token_account) = token_account {
                     println!("{:} ORE", token_account.token_amount.ui_amount_string);
@@ -0,0 +1,242 @@
+use futures::future::join_all;
+use solana_client::client_error::Result as ClientResult;
+use solana_program::{hash::Hash, instruction::Instruction};
+use solana_sdk::{
+    message::VersionedMessage,
+    packet::PACKET_DATA_SIZE,
+    signature::{Keypair, Signature, Signer},
+    transaction::VersionedTransaction,
+};
+
+use crate::{
+    cu_limits::{compute_unit_limit, MAX_CU_LIMIT},
+    nonce::NonceManager,
+    Miner,
+};
+
+// Instructions sent together in one transaction
+pub struct Batch {
+    pub ixs: Vec<Instruction>,
+    // Index of the first wallet instruction, after the compute budget instructions
+    pub offset: usize,
+    // Wallet owning each instruction from `offset` on
+    pub wallets: Vec<u64>,
+    // Wallets that sign, fee payer first
+    pub signers: Vec<u64>,
+}
+
+pub struct BatchOutcome {
+    pub wallets: Vec<u64>,
+    pub result: ClientResult<Signature>,
+}
+
+impl Miner {
+    // Packs each wallet's instruction, in order, into as few transactions as fit under the
+    // packet size and compute unit ceiling. `budget_ixs(n)` builds the compute budget
+    // instructions for a transaction of `n` wallet instructions. `payer` pays for every
+    // batch if given, otherwise the first wallet in each batch does.
+    pub fn pack_batches(
+        &self,
+        items: Vec<(u64, Instruction)>,
+        payer: Option<u64>,
+        budget_ixs: impl Fn(usize) -> Vec<Instruction>,
+    ) -> Vec<Batch> {
+        let mut batches: Vec<Batch> = Vec::new();
+        let mut current: Vec<(u64, Instruction)> = Vec::new();
+        for item in items {
+            current.push(item);
+            if current.len() > 1 && !self.fits(&self.build_batch(&current, payer, &budget_ixs)) {
+                let item = current.pop().unwrap();
+                batches.push(self.build_batch(&current, payer, &budget_ixs));
+                current = vec![item];
+            }
+        }
+        if !current.is_empty() {
+            batches.push(self.build_batch(&current, payer, &budget_ixs));
+        }
+        batches
+    }
+
+    fn build_batch(
+        &self,
+        items: &[(u64, Instruction)],
+        payer: Option<u64>,
+        budget_ixs: &impl Fn(usize) -> Vec<Instruction>,
+    ) -> Batch {
+        let mut ixs = budget_ixs(items.len());
+        let offset = ixs.len();
+        ixs.extend(items.iter().map(|(_, ix)| ix.clone()));
+        let wallets: Vec<u64> = items.iter().map(|(wallet, _)| *wallet).collect();
+        let mut signers: Vec<u64> = payer.into_iter().collect();
+        for wallet in wallets.iter() {
+            if !signers.contains(wallet) {
+                signers.push(*wallet);
+            }
+        }
+        Batch {
+            ixs,
+            offset,
+            wallets,
+            signers,
+        }
+    }
+
+    // Whether the batch fits in one packet, as the message it will be signed as, and
+    // within the most compute units a transaction may request
+    fn fits(&self, batch: &Batch) -> bool {
+        if compute_unit_limit(&batch.ixs).is_some_and(|units| units > MAX_CU_LIMIT) {
+            return false;
+        }
+        let payer = self.signer_by_number(batch.signers[0]).pubkey();
+        let nonce_pubkey = self
+            .nonce_manager
+            .as_ref()
+            .map(|_| NonceManager::address(&payer, 0));
+        let message = self.compile_message(&batch.ixs, &payer, nonce_pubkey.as_ref(), Hash::default());
+        transaction_size(message) <= PACKET_DATA_SIZE
+    }
+
+    pub fn batch_keypairs(&self, batch: &Batch) -> Vec<Keypair> {
+        batch.signers.iter().map(|wallet| self.signer_by_number(*wallet)).collect()
+    }
+
+    // Sends every batch at once and reports how each of them went. `dynamic_cus` sizes
+    // each batch's compute budget before sending it.
+    pub async fn send_batches(&self, batches: &[Batch], dynamic_cus: bool) -> Vec<BatchOutcome> {
+        let outcomes = join_all(batches.iter().map(|batch| async move {
+            let keypairs = self.batch_keypairs(batch);
+            let signers: Vec<&Keypair> = keypairs.iter().collect();
+            BatchOutcome {
+                wallets: batch.wallets.clone(),
+                result: self.send_and_confirm(&batch.ixs, dynamic_cus, false, signers).await,
+            }
+        }))
+        .await;
+        if outcomes.len() > 1 {
+            for (i, outcome) in outcomes.iter().enumerate() {
+                match &outcome.result {
+                    Ok(sig) => println!("Batch {}/{} (wallets {:?}) landed: {}", i + 1, outcomes.len(), outcome.wallets, sig),
+                    Err(err) => println!("Batch {}/{} (wallets {:?}) failed: {:?}", i + 1, outcomes.len(), outcome.wallets, err),
+                }
+            }
+        }
+        outcomes
+    }
+}
+
+// Serialized size of the signed transaction, which is what has to fit in a packet
+fn transaction_size(message: VersionedMessage) -> usize {
+    let tx = VersionedTransaction {
+        signatures: vec![Signature::default(); message.header().num_required_signatures as usize],
+        message,
+    };
+    bincode::serialized_size(&tx).map_or(usize::MAX, |size| size as usize)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use solana_program::{instruction::AccountMeta, pubkey::Pubkey};
+    use solana_sdk::{commitment_config::CommitmentConfig, compute_budget::ComputeBudgetInstruction, signature::write_keypair_file};
+
+    use super::*;
+    use crate::{budget::SpendBudget, priority_fee::PriorityFee, rpc_pool::RpcPool, FeeConfig, Transport};
+
+    // A miner with wallets 1 to 5 backed by fresh keypair files. Nothing here touches the network.
+    fn miner() -> (Miner, Vec<Pubkey>) {
+        let dir = std::env::temp_dir().join(format!("ore-batch-test-{}", Pubkey::new_unique()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut paths = Vec::new();
+        let mut pubkeys = Vec::new();
+        for wallet in 1..6 {
+            let keypair = Keypair::new();
+            let path = dir.join(format!("wallet{}.json", wallet));
+            write_keypair_file(&keypair, &path).unwrap();
+            paths.push(Some(path.to_string_lossy().into_owned()));
+            pubkeys.push(keypair.pubkey());
+        }
+        let transport = Transport {
+            rpc_pool: RpcPool::new(vec!["http://127.0.0.1:8899".into()], CommitmentConfig::confirmed()),
+            tpu_client: None,
+            bundle_sender: None,
+            lookup_tables: Vec::new(),
+            nonce_accounts: None,
+            simulator: None,
+        };
+        let fees = FeeConfig {
+            priority_fee: PriorityFee::Fixed(0),
+            max_priority_fee: None,
+            fee_escalation: None,
+            budget: SpendBudget::new(None, None),
+            cu_margin: 10,
+        };
+        let [p1, p2, p3, p4, p5]: [Option<String>; 5] = paths.try_into().unwrap();
+        (Miner::new(transport, fees, p1, p2, p3, p4, p5), pubkeys)
+    }
+
+    fn wallet_ix(program_id: Pubkey, wallet: Pubkey, data_len: usize) -> Instruction {
+        Instruction::new_with_bytes(program_id, &vec![0; data_len], vec![AccountMeta::new(wallet, true)])
+    }
+
+    fn budget_ixs(units_per_ix: u32) -> impl Fn(usize) -> Vec<Instruction> {
+        move |n| {
+            vec![
+                ComputeBudgetInstruction::set_compute_unit_limit(units_per_ix * n as u32),
+                ComputeBudgetInstruction::set_compute_unit_price(1),
+            ]
+        }
+    }
+
+    fn wallets(batches: &[Batch]) -> Vec<Vec<u64>> {
+        batches.iter().map(|batch| batch.wallets.clone()).collect()
+    }
+
+    #[test]
+    fn splits_batches_that_would_not_fit_in_a_packet() {
+        let (miner, pubkeys) = miner();
+        let program_id = Pubkey::new_unique();
+        // Two of these fit in a packet, three don't
+        let items = (1..4).map(|wallet| (wallet, wallet_ix(program_id, pubkeys[wallet as usize - 1], 400))).collect();
+
+        let batches = miner.pack_batches(items, None, budget_ixs(1_000));
+
+        assert_eq!(wallets(&batches), vec![vec![1, 2], vec![3]]);
+        assert!(batches.iter().all(|batch| miner.fits(batch)));
+        // The first wallet in each batch pays for it
+        assert_eq!(batches[1].signers, vec![3]);
+    }
+
+    #[test]
+    fn splits_batches_over_the_compute_unit_ceiling() {
+        let (miner, pubkeys) = miner();
+        let program_id = Pubkey::new_unique();
+        let items = (1..6).map(|wallet| (wallet, wallet_ix(program_id, pubkeys[wallet as usize - 1], 8))).collect();
+
+        // Three instructions would ask for 1.8M compute units
+        let batches = miner.pack_batches(items, None, budget_ixs(600_000));
+
+        assert_eq!(wallets(&batches), vec![vec![1, 2], vec![3, 4], vec![5]]);
+        assert_eq!(compute_unit_limit(&batches[0].ixs), Some(1_200_000));
+        assert_eq!(compute_unit_limit(&batches[2].ixs), Some(600_000));
+    }
+
+    #[test]
+    fn lists_each_signer_once_after_a_fixed_payer() {
+        let (miner, pubkeys) = miner();
+        let program_id = Pubkey::new_unique();
+        let items = vec![
+            (2, wallet_ix(program_id, pubkeys[1], 8)),
+            (1, wallet_ix(program_id, pubkeys[0], 8)),
+            (2, wallet_ix(program_id, pubkeys[1], 16)),
+        ];
+
+        let batches = miner.pack_batches(items, Some(1), budget_ixs(1_000));
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].signers, vec![1, 2]);
+        assert_eq!(batches[0].wallets, vec![2, 1, 2]);
+        assert_eq!(batches[0].offset, 2);
+        assert_eq!(batches[0].ixs.len(), 5);
+        assert_eq!(miner.batch_keypairs(&batches[0])[0].pubkey(), pubkeys[0]);
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    rpc_config::RpcTransactionConfig,
+};
+use solana_program::{instruction::Instruction, native_token::lamports_to_sol};
+use solana_sdk::{commitment_config::CommitmentConfig, signature::Signature};
+use solana_transaction_status::UiTransactionEncoding;
+
+use crate::{cu_limits::compute_unit_limit, priority_fee::compute_unit_price, Miner};
+
+pub const LAMPORTS_PER_SIGNATURE: u64 = 5000;
+
+// Compute units the runtime grants each instruction without a compute budget instruction
+const DEFAULT_INSTRUCTION_UNITS: u64 = 200_000;
+const MAX_TRANSACTION_UNITS: u64 = 1_400_000;
+
+const MICRO_LAMPORTS_PER_LAMPORT: u128 = 1_000_000;
+
+// Caps on what the session may spend on transaction fees
+pub struct SpendBudget {
+    pub max_spend: Option<u64>,
+    pub max_fee_per_tx: Option<u64>,
+    spent: AtomicU64,
+}
+
+impl SpendBudget {
+    pub fn new(max_spend: Option<u64>, max_fee_per_tx: Option<u64>) -> Self {
+        Self {
+            max_spend,
+            max_fee_per_tx,
+            spent: AtomicU64::new(0),
+        }
+    }
+
+    pub fn spent(&self) -> u64 {
+        self.spent.load(Ordering::Relaxed)
+    }
+
+    pub fn record(&self, fee: u64) {
+        self.spent.fetch_add(fee, Ordering::Relaxed);
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.max_spend.is_some_and(|max_spend| self.spent() >= max_spend)
+    }
+
+    // Returns why a transaction costing `fee` lamports may not be sent, if it may not
+    pub fn check(&self, fee: u64) -> Result<(), String> {
+        self.check_per_tx(fee)?;
+        self.check_remaining(fee)
+    }
+
+    // Whether a single transaction's fee stays under the max fee per transaction
+    pub fn check_per_tx(&self, fee: u64) -> Result<(), String> {
+        if let Some(max_fee_per_tx) = self.max_fee_per_tx {
+            if fee > max_fee_per_tx {
+                return Err(format!(
+                    "Transaction fee of {} lamports exceeds the max fee per transaction of {} lamports",
+                    fee, max_fee_per_tx
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Whether `fees` lamports, over however many transactions, fit in what's left of the
+    // session's spend budget
+    pub fn check_remaining(&self, fees: u64) -> Result<(), String> {
+        if let Some(max_spend) = self.max_spend {
+            if self.spent().saturating_add(fees) > max_spend {
+                return Err(format!(
+                    "SOL spend budget used up ({} of {} SOL spent)",
+                    lamports_to_sol(self.spent()),
+                    lamports_to_sol(max_spend)
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// The most a transaction can cost: its signature fees plus the priority fee on its full
+// compute unit limit
+pub fn estimate_fee(ixs: &[Instruction], signatures: usize) -> u64 {
+    let units = match compute_unit_limit(ixs) {
+        Some(units) => units as u64,
+        None => (DEFAULT_INSTRUCTION_UNITS * ixs.len() as u64).min(MAX_TRANSACTION_UNITS),
+    };
+    let price = compute_unit_price(ixs).unwrap_or(0);
+    let priority_fee = (units as u128 * price as u128).div_ceil(MICRO_LAMPORTS_PER_LAMPORT);
+    LAMPORTS_PER_SIGNATURE * signatures as u64 + priority_fee as u64
+}
+
+impl Miner {
+    // The fee actually charged for a landed transaction
+    pub async fn get_transaction_fee(&self, sig: &Signature) -> ClientResult<u64> {
+        let tx = self
+            .rpc_pool
+            .read(|client| async move {
+                client
+                    .get_transaction_with_config(
+                        sig,
+                        RpcTransactionConfig {
+                            encoding: Some(UiTransactionEncoding::Json),
+                            commitment: Some(CommitmentConfig::confirmed()),
+                            max_supported_transaction_version: Some(0),
+                        },
+                    )
+                    .await
+            })
+            .await?;
+        tx.transaction.meta.map(|meta| meta.fee).ok_or(ClientError {
+            request: None,
+            kind: ClientErrorKind::Custom(format!("Transaction {} has no status meta", sig)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_each_transaction_fee() {
+        let budget = SpendBudget::new(None, Some(10_000));
+        assert!(budget.check(10_000).is_ok());
+        assert!(budget.check(10_001).is_err());
+    }
+
+    #[test]
+    fn batch_totals_only_count_against_the_session_budget() {
+        // Three batches of 8000 lamports each stay under the per transaction cap, even
+        // though together they exceed it
+        let budget = SpendBudget::new(Some(30_000), Some(10_000));
+        assert!(budget.check_per_tx(8_000).is_ok());
+        assert!(budget.check_remaining(24_000).is_ok());
+
+        budget.record(10_000);
+        assert!(budget.check_remaining(24_000).is_err());
+        assert!(budget.check(8_000).is_ok());
+    }
+
+    #[test]
+    fn exhausted_once_spent() {
+        let budget = SpendBudget::new(Some(10_000), None);
+        budget.record(9_999);
+        assert!(!budget.exhausted());
+        budget.record(1);
+        assert!(budget.exhausted());
+        assert!(budget.check(1).is_err());
+    }
+}
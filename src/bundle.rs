@@ -0,0 +1,173 @@
+use serde_json::{json, Value};
+use solana_client::client_error::{reqwest, ClientError, ClientErrorKind, Result as ClientResult};
+use solana_program::{hash::Hash, pubkey::Pubkey, system_instruction};
+use solana_sdk::{
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+
+use crate::budget::LAMPORTS_PER_SIGNATURE;
+
+// Submits transactions as bundles to a block engine's JSON-RPC `sendBundle` method. Each
+// bundle carries a separate tip transfer, and lands all at once or not at all.
+pub struct BundleSender {
+    pub url: String,
+    pub tip_lamports: u64,
+    pub tip_account: Pubkey,
+    http: reqwest::Client,
+}
+
+impl BundleSender {
+    pub fn new(url: String, tip_lamports: u64, tip_account: Pubkey) -> Self {
+        Self {
+            url,
+            tip_lamports,
+            tip_account,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    // What a landed bundle costs on top of its main transaction
+    pub fn tip_cost(&self) -> u64 {
+        self.tip_lamports + LAMPORTS_PER_SIGNATURE
+    }
+
+    pub fn tip_transaction(&self, payer: &Keypair, blockhash: Hash) -> Transaction {
+        let ix = system_instruction::transfer(&payer.pubkey(), &self.tip_account, self.tip_lamports);
+        Transaction::new_signed_with_payer(&[ix], Some(&payer.pubkey()), &[payer], blockhash)
+    }
+
+    // Sends serialized transactions as one bundle and returns the bundle id
+    pub async fn send(&self, wire_txs: &[Vec<u8>]) -> ClientResult<String> {
+        let encoded: Vec<String> = wire_txs
+            .iter()
+            .map(|wire_tx| bs58::encode(wire_tx).into_string())
+            .collect();
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "sendBundle",
+            "params": [encoded],
+        });
+        let response = self
+            .http
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(request.to_string())
+            .send()
+            .await?;
+        let body: Value = serde_json::from_str(&response.text().await?)?;
+        if let Some(error) = body.get("error") {
+            return Err(bundle_error(format!("Bundle rejected: {}", error)));
+        }
+        body["result"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| bundle_error(format!("Unexpected sendBundle response: {}", body)))
+    }
+}
+
+fn bundle_error(msg: String) -> ClientError {
+    ClientError {
+        request: None,
+        kind: ClientErrorKind::Custom(msg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread::{self, JoinHandle},
+    };
+
+    use super::*;
+
+    // Serves a single HTTP request with `response` as the body, handing back the request body
+    fn mock_block_engine(response: &'static str) -> (String, JoinHandle<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .unwrap();
+            serde_json::from_slice(&body).unwrap()
+        });
+        (url, server)
+    }
+
+    fn bundle_sender(url: String) -> BundleSender {
+        BundleSender::new(url, 1000, Pubkey::new_unique())
+    }
+
+    fn custom_message(err: ClientError) -> String {
+        match err.kind() {
+            ClientErrorKind::Custom(msg) => msg.clone(),
+            kind => panic!("unexpected error kind: {:?}", kind),
+        }
+    }
+
+    #[tokio::test]
+    async fn returns_the_bundle_id() {
+        let (url, server) = mock_block_engine(r#"{"jsonrpc":"2.0","result":"bundle-id","id":1}"#);
+        let bundle_id = bundle_sender(url).send(&[vec![1, 2, 3], vec![4, 5]]).await.unwrap();
+        assert_eq!(bundle_id, "bundle-id");
+
+        let request = server.join().unwrap();
+        assert_eq!(request["method"], "sendBundle");
+        assert_eq!(
+            request["params"],
+            json!([[bs58::encode([1u8, 2, 3]).into_string(), bs58::encode([4u8, 5]).into_string()]])
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_rejected_bundles() {
+        let (url, server) = mock_block_engine(
+            r#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"bundle contains an expired blockhash"},"id":1}"#,
+        );
+        let err = bundle_sender(url).send(&[vec![1, 2, 3]]).await.unwrap_err();
+        let msg = custom_message(err);
+        assert!(msg.starts_with("Bundle rejected"), "{}", msg);
+        assert!(msg.contains("expired blockhash"), "{}", msg);
+        server.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_responses_without_a_result() {
+        let (url, server) = mock_block_engine(r#"{"jsonrpc":"2.0","id":1}"#);
+        let err = bundle_sender(url).send(&[vec![1, 2, 3]]).await.unwrap_err();
+        assert!(custom_message(err).starts_with("Unexpected sendBundle response"));
+        server.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_responses() {
+        let (url, server) = mock_block_engine("<html>Bad Gateway</html>");
+        let err = bundle_sender(url).send(&[vec![1, 2, 3]]).await.unwrap_err();
+        assert!(matches!(err.kind(), ClientErrorKind::SerdeJson(_)), "{:?}", err);
+        server.join().unwrap();
+    }
+}
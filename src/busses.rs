@@ -1,26 +1,188 @@
-use ore::{state::Bus, utils::AccountDeserialize, BUS_ADDRESSES, TOKEN_DECIMALS};
+use ore::{state::Bus, BUS_COUNT, TOKEN_DECIMALS};
+use rand::Rng;
 use solana_client::client_error::Result;
 
 use crate::Miner;
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum BusPolicy {
+    // Prefer the bus with the most remaining rewards
+    MaxRewards,
+    // Pick randomly, weighted by remaining rewards
+    WeightedRandom,
+    // Prefer the bus drained the least since the last poll
+    LeastContended,
+}
+
+pub enum BusSelection {
+    // The bus each wallet should mine on, in wallet order
+    Assigned(Vec<Bus>),
+    Depleted,
+}
+
+// Ranks busses according to a policy, remembering the last seen rewards to estimate contention
+pub struct BusSelector {
+    policy: BusPolicy,
+    last_rewards: [Option<u64>; BUS_COUNT],
+}
+
+impl BusSelector {
+    pub fn new(policy: BusPolicy) -> Self {
+        Self {
+            policy,
+            last_rewards: [None; BUS_COUNT],
+        }
+    }
+
+    // Returns busses holding more than `min_rewards`, most preferred first
+    pub fn rank(&mut self, busses: &[Bus], min_rewards: u64) -> Vec<Bus> {
+        let mut eligible: Vec<Bus> = busses
+            .iter()
+            .filter(|bus| bus.rewards.gt(&min_rewards))
+            .copied()
+            .collect();
+        match self.policy {
+            BusPolicy::MaxRewards => {
+                eligible.sort_by(|a, b| b.rewards.cmp(&a.rewards));
+            }
+            BusPolicy::WeightedRandom => {
+                let mut rng = rand::thread_rng();
+                let mut ranked = Vec::with_capacity(eligible.len());
+                while !eligible.is_empty() {
+                    let total: u64 = eligible.iter().map(|bus| bus.rewards).sum();
+                    let mut pick = rng.gen_range(0..total.max(1));
+                    let mut idx = eligible.len() - 1;
+                    for (i, bus) in eligible.iter().enumerate() {
+                        if pick < bus.rewards {
+                            idx = i;
+                            break;
+                        }
+                        pick -= bus.rewards;
+                    }
+                    ranked.push(eligible.remove(idx));
+                }
+                eligible = ranked;
+            }
+            BusPolicy::LeastContended => {
+                let drained = |bus: &Bus| match self.last_rewards[bus.id as usize] {
+                    Some(last) => last.saturating_sub(bus.rewards),
+                    None => 0,
+                };
+                eligible.sort_by(|a, b| {
+                    drained(a)
+                        .cmp(&drained(b))
+                        .then(b.rewards.cmp(&a.rewards))
+                });
+            }
+        }
+        for bus in busses {
+            self.last_rewards[bus.id as usize] = Some(bus.rewards);
+        }
+        eligible
+    }
+
+    // Spreads `wallets` mine instructions over the busses the policy prefers most. Wallets
+    // take the busses in rank order, moving on to the next one each time, and skip any
+    // bus the wallets already assigned to it would drain.
+    pub fn select(
+        &mut self,
+        busses: &[Bus],
+        wallets: usize,
+        reward_rate: u64,
+        min_rewards: u64,
+    ) -> BusSelection {
+        let mut ranked = self.rank(busses, min_rewards);
+        if ranked.is_empty() {
+            return BusSelection::Depleted;
+        }
+        ranked.truncate(wallets.max(1));
+        let mut remaining: Vec<u64> = ranked.iter().map(|bus| bus.rewards).collect();
+        let mut assignments = Vec::with_capacity(wallets);
+        let mut next = 0;
+        for _ in 0..wallets {
+            // Fall back to the fullest bus once none can cover another wallet
+            let idx = (0..ranked.len())
+                .map(|offset| (next + offset) % ranked.len())
+                .find(|i| remaining[*i] >= reward_rate)
+                .unwrap_or_else(|| {
+                    (0..ranked.len()).max_by_key(|i| remaining[*i]).unwrap_or_default()
+                });
+            remaining[idx] = remaining[idx].saturating_sub(reward_rate);
+            assignments.push(ranked[idx]);
+            next = idx + 1;
+        }
+        BusSelection::Assigned(assignments)
+    }
+}
+
 impl Miner {
     pub async fn busses(&self) {
-        let client = self.rpc_client.clone();
-        for address in BUS_ADDRESSES.iter() {
-            let data = client.get_account_data(address).await.unwrap();
-            match Bus::try_from_bytes(&data) {
-                Ok(bus) => {
+        match self.get_busses().await {
+            Ok(busses) => {
+                for bus in busses {
                     let rewards = (bus.rewards as f64) / 10f64.powf(TOKEN_DECIMALS as f64);
                     println!("Bus {}: {:} ORE", bus.id, rewards);
                 }
-                Err(_) => {}
             }
+            Err(err) => println!("Error: {:?}", err),
+        }
+    }
+
+    pub async fn get_busses(&self) -> Result<Vec<Bus>> {
+        self.chain.busses().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bus(id: u64, rewards: u64) -> Bus {
+        Bus { id, rewards }
+    }
+
+    fn assigned_ids(selection: BusSelection) -> Vec<u64> {
+        match selection {
+            BusSelection::Assigned(busses) => busses.iter().map(|bus| bus.id).collect(),
+            BusSelection::Depleted => panic!("busses reported depleted"),
         }
     }
 
-    pub async fn get_bus(&self, id: usize) -> Result<Bus> {
-        let client = self.rpc_client.clone();
-        let data = client.get_account_data(&BUS_ADDRESSES[id]).await?;
-        Ok(*Bus::try_from_bytes(&data).unwrap())
+    #[test]
+    fn assigns_in_rank_order() {
+        let busses = [bus(0, 500), bus(1, 300), bus(2, 400)];
+        let mut selector = BusSelector::new(BusPolicy::MaxRewards);
+        assert_eq!(assigned_ids(selector.select(&busses, 3, 10, 0)), vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn keeps_least_contended_order() {
+        let mut selector = BusSelector::new(BusPolicy::LeastContended);
+        selector.rank(&[bus(0, 1000), bus(1, 800)], 0);
+
+        // Bus 0 was drained the most, so bus 1 goes first despite holding less
+        let busses = [bus(0, 900), bus(1, 790)];
+        assert_eq!(assigned_ids(selector.select(&busses, 2, 10, 0)), vec![1, 0]);
+    }
+
+    #[test]
+    fn skips_busses_that_would_be_drained() {
+        // Bus 1 can't cover a wallet, so bus 0 takes all of them
+        let busses = [bus(0, 30), bus(1, 5)];
+        let mut selector = BusSelector::new(BusPolicy::MaxRewards);
+        assert_eq!(assigned_ids(selector.select(&busses, 3, 10, 0)), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn falls_back_to_fullest_bus() {
+        let busses = [bus(0, 8), bus(1, 6)];
+        let mut selector = BusSelector::new(BusPolicy::MaxRewards);
+        assert_eq!(assigned_ids(selector.select(&busses, 2, 10, 0)), vec![0, 1]);
+    }
+
+    #[test]
+    fn reports_depleted_busses() {
+        let mut selector = BusSelector::new(BusPolicy::MaxRewards);
+        assert!(matches!(selector.select(&[bus(0, 5)], 1, 10, 10), BusSelection::Depleted));
     }
 }
@@ -0,0 +1,297 @@
+use std::{sync::{Arc, Mutex}, time::Duration};
+
+use futures::future::BoxFuture;
+use ore::{
+    state::{Bus, Proof, Treasury},
+    utils::AccountDeserialize,
+    BUS_ADDRESSES,
+};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    rpc_config::RpcSimulateTransactionConfig,
+};
+use solana_program::{address_lookup_table::AddressLookupTableAccount, hash::Hash, instruction::Instruction, pubkey::Pubkey};
+use solana_sdk::{
+    clock::Clock,
+    instruction::InstructionError,
+    signature::{Keypair, Signature, Signer},
+    transaction::{TransactionError, VersionedTransaction},
+};
+
+use crate::{
+    alt::compile_message,
+    rpc_pool::RpcPool,
+    simulate::SimulatedChain,
+    utils::{get_clock_account, get_proof, get_treasury},
+    Miner,
+};
+
+const FETCH_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// What simulating a transaction reported
+pub struct Simulation {
+    pub err: Option<TransactionError>,
+    pub units_consumed: Option<u64>,
+    pub logs: Option<Vec<String>>,
+}
+
+// The cluster the mine loop reads from and waits on. `RpcChain` talks to the configured
+// endpoints, `SimulatedChain` runs the program in-process for dry runs.
+pub trait Chain: Send + Sync {
+    fn treasury(&self) -> BoxFuture<'_, ClientResult<Treasury>>;
+
+    fn clock(&self) -> BoxFuture<'_, ClientResult<Clock>>;
+
+    fn proof(&self, authority: Pubkey) -> BoxFuture<'_, ClientResult<Proof>>;
+
+    fn busses(&self) -> BoxFuture<'_, ClientResult<Vec<Bus>>>;
+
+    fn balance(&self, pubkey: Pubkey) -> BoxFuture<'_, ClientResult<u64>>;
+
+    // Token amount held by a token account, or None if it doesn't exist
+    fn token_balance(&self, token_account: Pubkey) -> BoxFuture<'_, ClientResult<Option<u64>>>;
+
+    // Priority fees recently paid by transactions writing to `accounts`
+    fn recent_priority_fees(&self, accounts: Vec<Pubkey>) -> BoxFuture<'_, ClientResult<Vec<u64>>>;
+
+    // Runs a transaction without landing it
+    fn simulate<'a>(&'a self, ixs: &'a [Instruction], signers: &'a [&'a Keypair]) -> BoxFuture<'a, ClientResult<Simulation>>;
+
+    // Lands a transaction right away on chains that run in-process. Chains behind a
+    // network return None, and the transaction is broadcast instead.
+    fn process(&self, ixs: &[Instruction], signers: &[Pubkey]) -> Option<Result<Signature, (usize, String)>>;
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()>;
+
+    // Unix timestamp on the chain's clock
+    fn now(&self) -> i64;
+}
+
+pub struct RpcChain {
+    rpc_pool: Arc<RpcPool>,
+    lookup_tables: Vec<AddressLookupTableAccount>,
+}
+
+impl RpcChain {
+    pub fn new(rpc_pool: Arc<RpcPool>, lookup_tables: Vec<AddressLookupTableAccount>) -> Self {
+        Self {
+            rpc_pool,
+            lookup_tables,
+        }
+    }
+}
+
+impl Chain for RpcChain {
+    fn treasury(&self) -> BoxFuture<'_, ClientResult<Treasury>> {
+        Box::pin(self.rpc_pool.read(|client| async move { get_treasury(&client).await }))
+    }
+
+    fn clock(&self) -> BoxFuture<'_, ClientResult<Clock>> {
+        Box::pin(self.rpc_pool.read(|client| async move { get_clock_account(&client).await }))
+    }
+
+    fn proof(&self, authority: Pubkey) -> BoxFuture<'_, ClientResult<Proof>> {
+        Box::pin(self.rpc_pool.read(move |client| async move { get_proof(&client, authority).await }))
+    }
+
+    // Fetches all busses in a single request
+    fn busses(&self) -> BoxFuture<'_, ClientResult<Vec<Bus>>> {
+        Box::pin(async move {
+            let accounts = self
+                .rpc_pool
+                .read(|client| async move { client.get_multiple_accounts(&BUS_ADDRESSES).await })
+                .await?;
+            Ok(accounts
+                .into_iter()
+                .flatten()
+                .filter_map(|account| Bus::try_from_bytes(&account.data).ok().copied())
+                .collect())
+        })
+    }
+
+    fn balance(&self, pubkey: Pubkey) -> BoxFuture<'_, ClientResult<u64>> {
+        Box::pin(self.rpc_pool.read(move |client| async move { client.get_balance(&pubkey).await }))
+    }
+
+    fn token_balance(&self, token_account: Pubkey) -> BoxFuture<'_, ClientResult<Option<u64>>> {
+        Box::pin(async move {
+            let token_account = self
+                .rpc_pool
+                .read(move |client| async move { client.get_token_account(&token_account).await })
+                .await?;
+            Ok(token_account.and_then(|token_account| token_account.token_amount.amount.parse().ok()))
+        })
+    }
+
+    fn recent_priority_fees(&self, accounts: Vec<Pubkey>) -> BoxFuture<'_, ClientResult<Vec<u64>>> {
+        Box::pin(async move {
+            let accounts = &accounts;
+            let recent_fees = self
+                .rpc_pool
+                .read(|client| async move { client.get_recent_prioritization_fees(accounts).await })
+                .await?;
+            Ok(recent_fees.iter().map(|fee| fee.prioritization_fee).collect())
+        })
+    }
+
+    fn simulate<'a>(&'a self, ixs: &'a [Instruction], signers: &'a [&'a Keypair]) -> BoxFuture<'a, ClientResult<Simulation>> {
+        Box::pin(async move {
+            // The endpoint swaps in a recent blockhash, so there's no need to fetch one
+            let msg = compile_message(ixs, &signers[0].pubkey(), None, Hash::default(), &self.lookup_tables);
+            let tx = VersionedTransaction::try_new(msg, signers)
+                .map_err(|err| custom_error(format!("Failed to sign transaction: {}", err)))?;
+            let tx = &tx;
+            let result = self
+                .rpc_pool
+                .read(|client| async move {
+                    let config = RpcSimulateTransactionConfig {
+                        sig_verify: false,
+                        replace_recent_blockhash: true,
+                        commitment: Some(client.commitment()),
+                        ..RpcSimulateTransactionConfig::default()
+                    };
+                    client.simulate_transaction_with_config(tx, config).await
+                })
+                .await?
+                .value;
+            Ok(Simulation {
+                err: result.err,
+                units_consumed: result.units_consumed,
+                logs: result.logs,
+            })
+        })
+    }
+
+    fn process(&self, _ixs: &[Instruction], _signers: &[Pubkey]) -> Option<Result<Signature, (usize, String)>> {
+        None
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn now(&self) -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+}
+
+impl Chain for Mutex<SimulatedChain> {
+    fn treasury(&self) -> BoxFuture<'_, ClientResult<Treasury>> {
+        let treasury = self.lock().unwrap().treasury();
+        Box::pin(async move { Ok(treasury) })
+    }
+
+    fn clock(&self) -> BoxFuture<'_, ClientResult<Clock>> {
+        let clock = self.lock().unwrap().clock();
+        Box::pin(async move { Ok(clock) })
+    }
+
+    fn proof(&self, authority: Pubkey) -> BoxFuture<'_, ClientResult<Proof>> {
+        let proof = self.lock().unwrap().proof(&authority);
+        Box::pin(async move { proof.ok_or_else(|| custom_error("Miner account not found".into())) })
+    }
+
+    fn busses(&self) -> BoxFuture<'_, ClientResult<Vec<Bus>>> {
+        let busses = self.lock().unwrap().busses();
+        Box::pin(async move { Ok(busses) })
+    }
+
+    fn balance(&self, pubkey: Pubkey) -> BoxFuture<'_, ClientResult<u64>> {
+        let balance = self.lock().unwrap().balance(&pubkey);
+        Box::pin(async move { Ok(balance) })
+    }
+
+    // Simulated token accounts always exist
+    fn token_balance(&self, token_account: Pubkey) -> BoxFuture<'_, ClientResult<Option<u64>>> {
+        let amount = self.lock().unwrap().token_balance(&token_account);
+        Box::pin(async move { Ok(Some(amount)) })
+    }
+
+    // Nobody else pays priority fees on the simulated chain
+    fn recent_priority_fees(&self, _accounts: Vec<Pubkey>) -> BoxFuture<'_, ClientResult<Vec<u64>>> {
+        Box::pin(async move { Ok(vec![]) })
+    }
+
+    // Runs the transaction on a copy of the chain. Compute units aren't modelled, so
+    // none are reported.
+    fn simulate<'a>(&'a self, ixs: &'a [Instruction], signers: &'a [&'a Keypair]) -> BoxFuture<'a, ClientResult<Simulation>> {
+        let signers: Vec<Pubkey> = signers.iter().map(|signer| signer.pubkey()).collect();
+        let mut fork = self.lock().unwrap().fork();
+        let simulation = match fork.process(ixs, &signers) {
+            Ok(_sig) => Simulation {
+                err: None,
+                units_consumed: None,
+                logs: None,
+            },
+            Err((idx, reason)) => Simulation {
+                err: Some(TransactionError::InstructionError(idx as u8, InstructionError::Custom(0))),
+                units_consumed: None,
+                logs: Some(vec![reason]),
+            },
+        };
+        Box::pin(async move { Ok(simulation) })
+    }
+
+    fn process(&self, ixs: &[Instruction], signers: &[Pubkey]) -> Option<Result<Signature, (usize, String)>> {
+        Some(self.lock().unwrap().process(ixs, signers))
+    }
+
+    // Just moves the simulated clock forward
+    fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()> {
+        let seconds = duration.as_millis().div_ceil(1000) as i64;
+        self.lock().unwrap().advance(seconds);
+        Box::pin(async {})
+    }
+
+    fn now(&self) -> i64 {
+        self.lock().unwrap().clock().unix_timestamp
+    }
+}
+
+fn custom_error(msg: String) -> ClientError {
+    ClientError {
+        request: None,
+        kind: ClientErrorKind::Custom(msg),
+    }
+}
+
+// Chain reads and waits used by the mine loop
+impl Miner {
+    pub async fn fetch_treasury(&self) -> Treasury {
+        // Keep retrying through outages rather than ending the session
+        loop {
+            match self.chain.treasury().await {
+                Ok(treasury) => return treasury,
+                Err(err) => {
+                    println!("Error fetching treasury, retrying: {:?}", err);
+                    self.chain.sleep(FETCH_RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+
+    pub async fn fetch_clock(&self) -> Clock {
+        loop {
+            match self.chain.clock().await {
+                Ok(clock) => return clock,
+                Err(err) => {
+                    println!("Error fetching clock, retrying: {:?}", err);
+                    self.chain.sleep(FETCH_RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+
+    pub async fn fetch_proof(&self, authority: Pubkey) -> ClientResult<Proof> {
+        self.chain.proof(authority).await
+    }
+
+    pub async fn fetch_balance(&self, pubkey: &Pubkey) -> ClientResult<u64> {
+        self.chain.balance(*pubkey).await
+    }
+
+    // Waits in real time, or just moves the simulated clock forward
+    pub async fn sleep(&self, duration: Duration) {
+        self.chain.sleep(duration).await
+    }
+}
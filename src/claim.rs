@@ -1,5 +1,4 @@
 use std::str::FromStr;
-use ore::{self, state::Proof, utils::AccountDeserialize};
 use solana_program::pubkey::Pubkey;
 use solana_sdk::{
     instruction::Instruction,
@@ -7,13 +6,34 @@ use solana_sdk::{
     signature::Signer,
     signer::keypair::Keypair
 };
-use crate::{cu_limits::CU_LIMIT_CLAIM, utils::proof_pubkey, utils::get_proof, Miner};
+use crate::{batch::BatchOutcome, cu_limits::claim_cu_limit, utils::treasury_tokens_pubkey, Miner, WALLETS};
+
+// Claim instructions for a set of wallets, packed into as few transactions as fit when sent
+pub struct ClaimBatch {
+    pub ixs: Vec<Instruction>,
+    pub wallets: Vec<u64>,
+    pub amounts: Vec<u64>,
+    pub amount: u64,
+}
+
+impl ClaimBatch {
+    // Total ORE claimed by the given wallets
+    pub fn amount_for(&self, wallets: &[u64]) -> f64 {
+        let amount: u64 = self
+            .wallets
+            .iter()
+            .zip(self.amounts.iter())
+            .filter(|(wallet, _)| wallets.contains(wallet))
+            .map(|(_, amount)| amount)
+            .sum();
+        (amount as f64) / (10f64.powf(ore::TOKEN_DECIMALS as f64))
+    }
+}
 
 impl Miner {
     pub async fn claim(&self, beneficiary: Option<String>, amount: Option<f64>) {
-        
+
         let signer1 = self.signer_by_number(1);
-        let client = self.rpc_client.clone();
         let beneficiary = match beneficiary {
             Some(beneficiary) => {
                 Pubkey::from_str(&beneficiary).expect("Failed to parse beneficiary address")
@@ -21,85 +41,80 @@ impl Miner {
             None => self.initialize_ata(&signer1).await,
         };
 
-        let cu_limit_ix = ComputeBudgetInstruction::set_compute_unit_limit(CU_LIMIT_CLAIM);
-        let cu_price_ix = ComputeBudgetInstruction::set_compute_unit_price(self.priority_fee);
-        let mut claim_ixs: Vec<Instruction> = Vec::new();
-        claim_ixs.push(cu_limit_ix);
-        claim_ixs.push(cu_price_ix);
-        
-        let mut has_ore_rewards = false;
-        let mut total_rewards_amount = 0;
-        let mut signerws = vec![1];
+        let batch = self.build_claims(beneficiary, amount, 0).await;
+
+        if !batch.wallets.is_empty() {
+            println!("Submitting claim transaction...");
+            for outcome in self.send_claims(&batch).await {
+                match outcome.result {
+                    Ok(sig) => {
+                        println!("{} Ore Claimed Successfully! to {} : {}", batch.amount_for(&outcome.wallets), beneficiary, sig);
+                    }
+                    Err(err) => {
+                        println!("Error claiming for wallets {:?}: {:?}", outcome.wallets, err);
+                    }
+                }
+            }
+        }
+    }
+
+    // Builds claim instructions for every wallet holding more than `threshold` claimable
+    // rewards. Claims `amount` ORE per wallet, or everything if no amount is given.
+    pub async fn build_claims(&self, beneficiary: Pubkey, amount: Option<f64>, threshold: u64) -> ClaimBatch {
+        let mut batch = ClaimBatch {
+            ixs: Vec::new(),
+            wallets: Vec::new(),
+            amounts: Vec::new(),
+            amount: 0,
+        };
 
-        for w in 1 .. 6 {
+        for w in 1..WALLETS + 1 {
             let signer = self.signer_by_number(w);
-            let pubkey = signer.pubkey();    
-            let proof = get_proof(&self.rpc_client, pubkey).await;
+            let pubkey = signer.pubkey();
+            let proof = match self.fetch_proof(pubkey).await {
+                Ok(proof) => proof,
+                Err(err) => {
+                    println!("Error looking up claimable rewards for address {}: {:?}", pubkey, err);
+                    continue;
+                }
+            };
             let rewardtotal = (proof.claimable_rewards as f64) / 10f64.powf(ore::TOKEN_DECIMALS as f64);
-            if rewardtotal == 0.0 {
+            if proof.claimable_rewards <= threshold {
                 println!("Nothing to claim for address {}", pubkey);
             }
             else {
-                has_ore_rewards = true;
                 println!("{} ORE to claim on address {}", rewardtotal, pubkey);
                 let amount = if let Some(amount) = amount {
                     (amount * 10f64.powf(ore::TOKEN_DECIMALS as f64)) as u64
                 } else {
-                    match client.get_account(&proof_pubkey(pubkey)).await {
-                        Ok(proof_account) => {
-                            let proof = Proof::try_from_bytes(&proof_account.data).unwrap();
-                            proof.claimable_rewards
-                        }
-                        Err(err) => {
-                            println!("Error looking up claimable rewards: {:?}", err);
-                            return;
-                        }
-                    }
+                    proof.claimable_rewards
                 };
-                total_rewards_amount += amount;
-                let ix = ore::instruction::claim(pubkey, beneficiary, amount);
-                claim_ixs.push(ix);
-                if w > 1 {
-                    signerws.push(w);
-                }
-            }
-        }
-
-        let amountf = (total_rewards_amount as f64) / (10f64.powf(ore::TOKEN_DECIMALS as f64));
-
-        if has_ore_rewards {
-            println!("Submitting claim transaction...");
-            let signer2 = self.signer_by_number(2);
-            let signer3 = self.signer_by_number(3);
-            let signer4 = self.signer_by_number(4);
-            let signer5 = self.signer_by_number(5);            
-            
-            let mut signers = vec![&signer1];
-            for w in 0..signerws.len() {
-                if signerws[w] == 2 { signers.push(&signer2); }
-                if signerws[w] == 3 { signers.push(&signer3); }
-                if signerws[w] == 4 { signers.push(&signer4); }
-                if signerws[w] == 5 { signers.push(&signer5); }
-            }
-
-            match self
-                .send_and_confirm(&claim_ixs.into_boxed_slice(), false, false, signers)
-                .await
-            {
-                Ok(sig) => {
-                    println!("{} Ore Claimed Successfully! to {} : {}", amountf, beneficiary, sig);
-                }
-                Err(err) => {
-                    println!("Error: {:?}", err);
-                }
+                batch.amount += amount;
+                batch.ixs.push(ore::instruction::claim(pubkey, beneficiary, amount));
+                batch.wallets.push(w);
+                batch.amounts.push(amount);
             }
         }
+        batch
     }
 
-    async fn initialize_ata(&self, signer: &Keypair) -> Pubkey {
-        // Initialize client.
-        let client = self.rpc_client.clone();
+    // Sends a claim batch, with wallet 1 paying the fees of every transaction
+    pub async fn send_claims(&self, batch: &ClaimBatch) -> Vec<BatchOutcome> {
+        let priority_fee = self
+            .get_priority_fee(&[ore::ID, ore::TREASURY_ADDRESS, treasury_tokens_pubkey()])
+            .await;
+        println!("Using priority fee: {} micro-lamports", priority_fee);
+        let items = batch.wallets.iter().copied().zip(batch.ixs.iter().cloned()).collect();
+        let batches = self.pack_batches(items, Some(1), |claims| {
+            vec![
+                ComputeBudgetInstruction::set_compute_unit_limit(claim_cu_limit(claims)),
+                ComputeBudgetInstruction::set_compute_unit_price(priority_fee),
+            ]
+        });
+        self.send_batches(&batches, true).await
+    }
 
+    pub async fn initialize_ata(&self, signer: &Keypair) -> Pubkey {
         let pubkey = signer.pubkey();
 
         // Build instructions.
@@ -109,7 +124,7 @@ impl Miner {
         );
 
         // Check if ata already exists
-        if let Ok(Some(_ata)) = client.get_token_account(&token_account_pubkey).await {
+        if let Ok(Some(_amount)) = self.chain.token_balance(token_account_pubkey).await {
             return token_account_pubkey;
         }
 
@@ -1,4 +1,300 @@
-pub const CU_LIMIT_CLAIM: u32 = 1_000 + (10_000 * 5);
+use std::{collections::HashMap, sync::Mutex};
+
+use solana_program::{instruction::Instruction, pubkey::Pubkey, system_program};
+use solana_sdk::{
+    compute_budget::{self, ComputeBudgetInstruction},
+    signature::Keypair,
+};
+
+use crate::Miner;
+
+// Fallback limits, used until a transaction has been simulated.
+// Rough ix costs based on what i see in the logs.
 pub const CU_LIMIT_RESET: u32 = 12_200;
-//pub const CU_LIMIT_MINE: u32 = 3200;
-pub const CU_LIMIT_MINE: u32 = 500 + (2300 * 5);  //rough ix cost based on what i see in the logs * number of wallets
+const CU_LIMIT_CLAIM_BASE: u32 = 1_000;
+const CU_PER_CLAIM: u32 = 10_000;
+const CU_LIMIT_MINE_BASE: u32 = 500;
+const CU_PER_MINE: u32 = 2300;
+
+pub const MAX_CU_LIMIT: u32 = 1_400_000;
+
+// Builtin programs charge a flat cost per instruction and don't log what they consume
+const BUILTIN_INSTRUCTION_UNITS: u64 = 150;
+
+// Compute budget instruction discriminator
+const SET_COMPUTE_UNIT_LIMIT: u8 = 2;
+
+pub fn claim_cu_limit(claims: usize) -> u32 {
+    CU_LIMIT_CLAIM_BASE + CU_PER_CLAIM * claims as u32
+}
+
+pub fn mine_cu_limit(mines: usize) -> u32 {
+    CU_LIMIT_MINE_BASE + CU_PER_MINE * mines as u32
+}
+
+// The compute unit limit set by a compute budget instruction in `ixs`, if any
+pub fn compute_unit_limit(ixs: &[Instruction]) -> Option<u32> {
+    ixs.iter().find_map(|ix| {
+        if ix.program_id != compute_budget::id() || ix.data.first() != Some(&SET_COMPUTE_UNIT_LIMIT) {
+            return None;
+        }
+        ix.data.get(1..5).map(|data| u32::from_le_bytes(data.try_into().unwrap()))
+    })
+}
+
+// Replaces the compute unit limit in `ixs`, adding one up front if there is none
+pub fn with_compute_unit_limit(ixs: &[Instruction], units: u32) -> Vec<Instruction> {
+    let limit_ix = ComputeBudgetInstruction::set_compute_unit_limit(units);
+    if compute_unit_limit(ixs).is_none() {
+        return std::iter::once(limit_ix).chain(ixs.iter().cloned()).collect();
+    }
+    ixs.iter()
+        .map(|ix| {
+            if ix.program_id == compute_budget::id() && ix.data.first() == Some(&SET_COMPUTE_UNIT_LIMIT) {
+                limit_ix.clone()
+            } else {
+                ix.clone()
+            }
+        })
+        .collect()
+}
+
+// Compute units each kind of instruction was seen to use, keyed by program and
+// instruction discriminator
+#[derive(Default)]
+pub struct CuCalibration {
+    units: Mutex<HashMap<(Pubkey, u8), u64>>,
+}
+
+impl CuCalibration {
+    // Units `ixs` should use, if every instruction in them has been measured
+    pub fn estimate(&self, ixs: &[Instruction]) -> Option<u64> {
+        let units = self.units.lock().unwrap();
+        ixs.iter()
+            .map(|ix| match calibration_key(ix) {
+                Some(key) => units.get(&key).copied(),
+                None => Some(BUILTIN_INSTRUCTION_UNITS),
+            })
+            .sum()
+    }
+
+    // Learns per-instruction costs from the logs of a successful simulation
+    pub fn record(&self, ixs: &[Instruction], logs: &[String]) {
+        let mut units = self.units.lock().unwrap();
+        let mut idx: Option<usize> = None;
+        let mut depth: u32 = 0;
+        for log in logs {
+            let Some(log) = log.strip_prefix("Program ") else {
+                continue;
+            };
+            // Skip whatever programs print themselves
+            if log.starts_with("log:") || log.starts_with("data:") || log.starts_with("return:") {
+                continue;
+            }
+            if let Some((_, invoke_depth)) = log.split_once(" invoke [") {
+                depth = invoke_depth.trim_end_matches(']').parse().unwrap_or(depth);
+                if depth == 1 {
+                    idx = Some(idx.map_or(0, |idx| idx + 1));
+                }
+            } else if log.ends_with(" success") || log.contains(" failed: ") {
+                depth = depth.saturating_sub(1);
+            } else if let Some((_, consumed)) = log.split_once(" consumed ") {
+                if depth != 1 {
+                    continue;
+                }
+                let Some(key) = idx.and_then(|idx| ixs.get(idx)).and_then(calibration_key) else {
+                    continue;
+                };
+                if let Some(consumed) = consumed.split(' ').next().and_then(|n| n.parse::<u64>().ok()) {
+                    let entry = units.entry(key).or_default();
+                    *entry = (*entry).max(consumed);
+                }
+            }
+        }
+    }
+}
+
+// Builtin instructions have a flat cost and aren't calibrated
+fn calibration_key(ix: &Instruction) -> Option<(Pubkey, u8)> {
+    if ix.program_id == compute_budget::id() || ix.program_id == system_program::id() {
+        return None;
+    }
+    Some((ix.program_id, ix.data.first().copied().unwrap_or_default()))
+}
+
+impl Miner {
+    // Sets the compute unit limit of `ixs` to what they are measured to use plus the
+    // configured margin. Simulates them if any instruction hasn't been measured yet, and
+    // keeps the existing limit if that fails.
+    pub async fn calibrate_cu_limit(&self, ixs: &[Instruction], signers: &[&Keypair]) -> Vec<Instruction> {
+        let units = match self.cu_calibration.estimate(ixs) {
+            Some(units) => units,
+            None => match self.simulate_cu_usage(ixs, signers).await {
+                Some(units) => units,
+                None => return ixs.to_vec(),
+            },
+        };
+        let units = units.saturating_mul(100 + self.cu_margin) / 100;
+        with_compute_unit_limit(ixs, units.min(MAX_CU_LIMIT as u64) as u32)
+    }
+
+    async fn simulate_cu_usage(&self, ixs: &[Instruction], signers: &[&Keypair]) -> Option<u64> {
+        let ixs = with_compute_unit_limit(ixs, MAX_CU_LIMIT);
+        let result = self.chain.simulate(&ixs, signers).await.ok()?;
+        if result.err.is_some() {
+            return None;
+        }
+        if let Some(logs) = &result.logs {
+            self.cu_calibration.record(&ixs, logs);
+        }
+        result.units_consumed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::instruction::AccountMeta;
+
+    use super::*;
+
+    const COMPUTE_BUDGET: &str = "ComputeBudget111111111111111111111111111111";
+    const TOKEN: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+
+    fn program_ix(program_id: Pubkey, discriminator: u8) -> Instruction {
+        Instruction::new_with_bytes(program_id, &[discriminator], vec![AccountMeta::new(Pubkey::new_unique(), true)])
+    }
+
+    fn budget_ixs() -> Vec<Instruction> {
+        vec![
+            ComputeBudgetInstruction::set_compute_unit_limit(200_000),
+            ComputeBudgetInstruction::set_compute_unit_price(1_000),
+        ]
+    }
+
+    // Logs of a simulated transaction: two compute budget instructions, then one
+    // instruction per program that CPIs into the token program and consumes `units`
+    fn simulation_logs(programs: &[(Pubkey, u64)]) -> Vec<String> {
+        let mut logs = vec![
+            format!("Program {} invoke [1]", COMPUTE_BUDGET),
+            format!("Program {} success", COMPUTE_BUDGET),
+            format!("Program {} invoke [1]", COMPUTE_BUDGET),
+            format!("Program {} success", COMPUTE_BUDGET),
+        ];
+        for (program_id, units) in programs {
+            logs.extend([
+                format!("Program {} invoke [1]", program_id),
+                "Program log: Instruction: Mine".to_string(),
+                format!("Program {} invoke [2]", TOKEN),
+                "Program log: Instruction: Transfer".to_string(),
+                format!("Program {} consumed 4645 of 190000 compute units", TOKEN),
+                format!("Program {} success", TOKEN),
+                "Program return: 11111111111111111111111111111111 AQ==".to_string(),
+                format!("Program {} consumed {} of 195355 compute units", program_id, units),
+                format!("Program {} success", program_id),
+            ]);
+        }
+        logs
+    }
+
+    #[test]
+    fn records_top_level_units_past_cpis() {
+        let program_id = Pubkey::new_unique();
+        let mut ixs = budget_ixs();
+        ixs.push(program_ix(program_id, 2));
+        let calibration = CuCalibration::default();
+
+        calibration.record(&ixs, &simulation_logs(&[(program_id, 12_345)]));
+
+        // Builtins are costed flat, the CPI's own units are part of its caller's
+        assert_eq!(calibration.estimate(&ixs), Some(2 * BUILTIN_INSTRUCTION_UNITS + 12_345));
+    }
+
+    #[test]
+    fn attributes_units_to_the_matching_instruction() {
+        let mine = Pubkey::new_unique();
+        let claim = Pubkey::new_unique();
+        let mut ixs = budget_ixs();
+        ixs.push(program_ix(mine, 2));
+        ixs.push(program_ix(claim, 3));
+        let calibration = CuCalibration::default();
+
+        calibration.record(&ixs, &simulation_logs(&[(mine, 3_000), (claim, 9_000)]));
+
+        assert_eq!(calibration.estimate(&[program_ix(mine, 2)]), Some(3_000));
+        assert_eq!(calibration.estimate(&[program_ix(claim, 3)]), Some(9_000));
+        // Same program, different instruction
+        assert_eq!(calibration.estimate(&[program_ix(mine, 3)]), None);
+    }
+
+    #[test]
+    fn keeps_the_highest_usage_seen() {
+        let program_id = Pubkey::new_unique();
+        let ixs = vec![program_ix(program_id, 2)];
+        let calibration = CuCalibration::default();
+        let logs = |units: u64| {
+            vec![
+                format!("Program {} invoke [1]", program_id),
+                format!("Program {} consumed {} of 200000 compute units", program_id, units),
+                format!("Program {} success", program_id),
+            ]
+        };
+
+        calibration.record(&ixs, &logs(5_000));
+        calibration.record(&ixs, &logs(4_000));
+
+        assert_eq!(calibration.estimate(&ixs), Some(5_000));
+    }
+
+    #[test]
+    fn stops_at_a_failed_instruction() {
+        let first = Pubkey::new_unique();
+        let second = Pubkey::new_unique();
+        let ixs = vec![program_ix(first, 2), program_ix(second, 2)];
+        let calibration = CuCalibration::default();
+        let logs = vec![
+            format!("Program {} invoke [1]", first),
+            format!("Program {} invoke [2]", TOKEN),
+            format!("Program {} consumed 3000 of 200000 compute units", TOKEN),
+            format!("Program {} failed: custom program error: 0x1", TOKEN),
+            format!("Program {} consumed 7000 of 200000 compute units", first),
+            format!("Program {} failed: custom program error: 0x1", first),
+        ];
+
+        calibration.record(&ixs, &logs);
+
+        assert_eq!(calibration.estimate(&ixs[..1]), Some(7_000));
+        assert_eq!(calibration.estimate(&ixs[1..]), None);
+    }
+
+    #[test]
+    fn estimates_nothing_before_recording() {
+        let calibration = CuCalibration::default();
+        assert_eq!(calibration.estimate(&budget_ixs()), Some(2 * BUILTIN_INSTRUCTION_UNITS));
+        assert_eq!(calibration.estimate(&[program_ix(Pubkey::new_unique(), 0)]), None);
+    }
+
+    #[test]
+    fn replaces_an_existing_limit_in_place() {
+        let program_id = Pubkey::new_unique();
+        let mut ixs = budget_ixs();
+        ixs.push(program_ix(program_id, 2));
+
+        let limited = with_compute_unit_limit(&ixs, 50_000);
+
+        assert_eq!(limited.len(), ixs.len());
+        assert_eq!(compute_unit_limit(&limited), Some(50_000));
+        assert_eq!(limited[1], ixs[1]);
+        assert_eq!(limited[2], ixs[2]);
+    }
+
+    #[test]
+    fn adds_a_limit_when_there_is_none() {
+        let ixs = vec![program_ix(Pubkey::new_unique(), 2)];
+
+        let limited = with_compute_unit_limit(&ixs, 50_000);
+
+        assert_eq!(limited.len(), 2);
+        assert_eq!(limited[0], ComputeBudgetInstruction::set_compute_unit_limit(50_000));
+        assert_eq!(limited[1], ixs[0]);
+    }
+}
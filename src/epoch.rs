@@ -0,0 +1,40 @@
+use ore::{state::Treasury, EPOCH_DURATION};
+use solana_sdk::clock::Clock;
+
+// Where the clock sits relative to the current epoch
+#[derive(Clone, Copy, Debug)]
+pub struct EpochTiming {
+    pub last_reset_at: i64,
+    pub now: i64,
+}
+
+impl EpochTiming {
+    pub fn new(treasury: &Treasury, clock: &Clock) -> Self {
+        Self::from_parts(treasury.last_reset_at, clock.unix_timestamp)
+    }
+
+    pub fn from_parts(last_reset_at: i64, now: i64) -> Self {
+        Self { last_reset_at, now }
+    }
+
+    pub fn elapsed(&self) -> i64 {
+        self.now.saturating_sub(self.last_reset_at).max(0)
+    }
+
+    // Seconds until the epoch can be reset, zero if it is already due
+    pub fn seconds_to_reset(&self) -> i64 {
+        self.last_reset_at
+            .saturating_add(EPOCH_DURATION)
+            .saturating_sub(self.now)
+            .max(0)
+    }
+
+    pub fn needs_reset(&self) -> bool {
+        self.now >= self.last_reset_at.saturating_add(EPOCH_DURATION)
+    }
+
+    // Whether the busses were refilled within the last `window` seconds
+    pub fn is_fresh(&self, window: i64) -> bool {
+        !self.needs_reset() && self.elapsed() < window
+    }
+}
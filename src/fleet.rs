@@ -0,0 +1,151 @@
+use solana_program::keccak::Hash as KeccakHash;
+
+// Consecutive errors after which a wallet is quarantined
+const QUARANTINE_ERRORS: u64 = 3;
+
+// Seconds of chain time a quarantined wallet sits out before it is retried
+const QUARANTINE_SECONDS: i64 = 300;
+
+// A mined hash and the nonce that produced it
+#[derive(Clone, Copy)]
+pub struct Solution {
+    pub hash: KeccakHash,
+    pub nonce: u64,
+}
+
+pub struct WalletState {
+    pub number: u64,
+    pub solution: Option<Solution>,
+    errors: u64,
+    // Chain timestamp the quarantine ends at
+    quarantined_until: Option<i64>,
+}
+
+impl WalletState {
+    pub fn is_healthy(&self) -> bool {
+        self.quarantined_until.is_none()
+    }
+}
+
+// Per-wallet mining state, so one misbehaving wallet doesn't stall the others
+pub struct Fleet {
+    pub wallets: Vec<WalletState>,
+}
+
+impl Fleet {
+    pub fn new(wallets: Vec<u64>) -> Self {
+        Self {
+            wallets: wallets
+                .into_iter()
+                .map(|number| WalletState {
+                    number,
+                    solution: None,
+                    errors: 0,
+                    quarantined_until: None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn get_mut(&mut self, number: u64) -> Option<&mut WalletState> {
+        self.wallets.iter_mut().find(|wallet| wallet.number == number)
+    }
+
+    // Healthy wallets that need a new solution
+    pub fn stale(&self) -> Vec<u64> {
+        self.wallets
+            .iter()
+            .filter(|wallet| wallet.is_healthy() && wallet.solution.is_none())
+            .map(|wallet| wallet.number)
+            .collect()
+    }
+
+    // Healthy wallets holding a solution ready to submit
+    pub fn ready(&self) -> Vec<u64> {
+        self.wallets
+            .iter()
+            .filter(|wallet| wallet.is_healthy() && wallet.solution.is_some())
+            .map(|wallet| wallet.number)
+            .collect()
+    }
+
+    pub fn record_success(&mut self, number: u64) {
+        if let Some(wallet) = self.get_mut(number) {
+            wallet.errors = 0;
+        }
+    }
+
+    // Counts an error against a wallet, quarantining it after too many in a row. `now`
+    // is the chain's timestamp, so simulated sessions quarantine in simulated time.
+    pub fn record_error(&mut self, number: u64, reason: &str, now: i64) {
+        if let Some(wallet) = self.get_mut(number) {
+            wallet.errors += 1;
+            println!("Wallet {} error: {}", number, reason);
+            if wallet.errors >= QUARANTINE_ERRORS {
+                println!("Quarantining wallet {} for {} seconds", number, QUARANTINE_SECONDS);
+                wallet.solution = None;
+                wallet.quarantined_until = Some(now + QUARANTINE_SECONDS);
+            }
+        }
+    }
+
+    // Returns quarantined wallets to the pool once their time is up
+    pub fn release_expired(&mut self, now: i64) {
+        for wallet in self.wallets.iter_mut() {
+            if let Some(until) = wallet.quarantined_until {
+                if now >= until {
+                    println!("Releasing wallet {} from quarantine", wallet.number);
+                    wallet.quarantined_until = None;
+                    wallet.errors = 0;
+                }
+            }
+        }
+    }
+
+    pub fn quarantined(&self) -> Vec<u64> {
+        self.wallets
+            .iter()
+            .filter(|wallet| !wallet.is_healthy())
+            .map(|wallet| wallet.number)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarantines_after_repeated_errors() {
+        let mut fleet = Fleet::new(vec![1, 2]);
+        for _ in 0..QUARANTINE_ERRORS {
+            fleet.record_error(1, "mine instruction failed", 1_000);
+        }
+        assert_eq!(fleet.quarantined(), vec![1]);
+        assert_eq!(fleet.stale(), vec![2]);
+    }
+
+    #[test]
+    fn success_resets_the_error_count() {
+        let mut fleet = Fleet::new(vec![1]);
+        for _ in 0..QUARANTINE_ERRORS - 1 {
+            fleet.record_error(1, "mine instruction failed", 1_000);
+        }
+        fleet.record_success(1);
+        fleet.record_error(1, "mine instruction failed", 1_000);
+        assert!(fleet.quarantined().is_empty());
+    }
+
+    #[test]
+    fn releases_once_chain_time_passes() {
+        let mut fleet = Fleet::new(vec![1]);
+        for _ in 0..QUARANTINE_ERRORS {
+            fleet.record_error(1, "mine instruction failed", 1_000);
+        }
+        fleet.release_expired(1_000 + QUARANTINE_SECONDS - 1);
+        assert_eq!(fleet.quarantined(), vec![1]);
+        fleet.release_expired(1_000 + QUARANTINE_SECONDS);
+        assert!(fleet.quarantined().is_empty());
+        assert_eq!(fleet.stale(), vec![1]);
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, FixedOffset, Utc};
+
+// Conditions that end a mining run early
+pub struct RunLimits {
+    pub max_iterations: Option<u64>,
+    pub until: Option<DateTime<FixedOffset>>,
+    pub deadline: Option<Instant>,
+    pub target_ore: Option<f64>,
+}
+
+impl RunLimits {
+    pub fn new(
+        max_iterations: Option<u64>,
+        until: Option<DateTime<FixedOffset>>,
+        duration: Option<Duration>,
+        target_ore: Option<f64>,
+    ) -> Self {
+        Self {
+            max_iterations,
+            until,
+            deadline: duration.map(|duration| Instant::now() + duration),
+            target_ore,
+        }
+    }
+
+    // Returns why the run should stop, if any limit other than the ORE target was reached
+    pub fn reached(&self, iterations: u64) -> Option<String> {
+        if let Some(max_iterations) = self.max_iterations {
+            if iterations >= max_iterations {
+                return Some(format!("Reached {} iterations", max_iterations));
+            }
+        }
+        if let Some(until) = self.until {
+            if Utc::now() >= until.with_timezone(&Utc) {
+                return Some(format!("Reached end time {}", until.to_rfc3339()));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Some("Reached run duration".to_string());
+            }
+        }
+        None
+    }
+
+    pub fn target_reached(&self, claimable_ore: f64) -> Option<String> {
+        match self.target_ore {
+            Some(target_ore) if claimable_ore >= target_ore => Some(format!(
+                "Reached target of {} ORE ({} ORE claimable)",
+                target_ore, claimable_ore
+            )),
+            _ => None,
+        }
+    }
+}
+
+// Parses durations like `90s`, `45m`, `8h` or `2d`. A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, "s"),
+    };
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration `{}`", s))?;
+    let seconds = match unit {
+        "s" => value,
+        "m" => value * 60,
+        "h" => value * 60 * 60,
+        "d" => value * 60 * 60 * 24,
+        _ => return Err(format!("invalid duration unit in `{}`, expected s, m, h or d", s)),
+    };
+    Ok(Duration::from_secs(seconds))
+}
@@ -1,13 +1,31 @@
+mod alt;
 mod balance;
+mod batch;
+mod budget;
+mod bundle;
 mod busses;
+mod chain;
 mod claim;
 mod cu_limits;
+mod epoch;
+mod fleet;
 #[cfg(feature = "admin")]
 mod initialize;
+mod limits;
+mod metrics;
 mod mine;
+mod nonce;
+mod priority_fee;
 mod register;
+mod reset;
 mod rewards;
+mod rpc_pool;
+mod schedule;
 mod send_and_confirm;
+mod session;
+mod simulate;
+mod strategy;
+mod tpu;
 mod treasury;
 #[cfg(feature = "admin")]
 mod update_admin;
@@ -15,14 +33,36 @@ mod update_admin;
 mod update_difficulty;
 mod utils;
 
-use std::sync::Arc;
+use std::{
+    sync::{atomic::{AtomicBool, AtomicU64}, Arc, Mutex},
+    time::Duration,
+};
 
+use budget::SpendBudget;
+use bundle::BundleSender;
+use busses::BusPolicy;
+use chain::{Chain, RpcChain};
+use chrono::{DateTime, FixedOffset};
 use clap::{command, Parser, Subcommand};
+use cu_limits::CuCalibration;
+use limits::parse_duration;
+use metrics::Metrics;
+use nonce::NonceManager;
+use priority_fee::{FeeEscalation, PriorityFee};
+use reset::ResetPolicy;
+use rpc_pool::RpcPool;
+use schedule::Schedule;
+use simulate::SimulatedChain;
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{
+    address_lookup_table::AddressLookupTableAccount,
     commitment_config::CommitmentConfig,
+    native_token::sol_to_lamports,
+    pubkey::Pubkey,
     signature::{read_keypair_file, Keypair},
 };
+use strategy::StrategyKind;
+use tpu::QuicTpuClient;
 
 const WALLETS: u64 = 5;
 
@@ -32,8 +72,22 @@ struct Miner {
     pub keypair_filepath3: Option<String>,
     pub keypair_filepath4: Option<String>,
     pub keypair_filepath5: Option<String>,
-    pub priority_fee: u64,
+    pub priority_fee: PriorityFee,
+    pub max_priority_fee: Option<u64>,
+    pub last_priority_fee: AtomicU64,
+    pub fee_escalation: Option<FeeEscalation>,
+    pub budget: SpendBudget,
+    pub cu_margin: u64,
+    pub cu_calibration: CuCalibration,
+    pub nonce_manager: Option<NonceManager>,
     pub rpc_client: Arc<RpcClient>,
+    pub rpc_pool: Arc<RpcPool>,
+    pub tpu_client: Option<QuicTpuClient>,
+    pub bundle_sender: Option<BundleSender>,
+    pub lookup_tables: Vec<AddressLookupTableAccount>,
+    pub metrics: Arc<Metrics>,
+    pub shutdown: Arc<AtomicBool>,
+    pub chain: Box<dyn Chain>,
 }
 
 #[derive(Parser, Debug)]
@@ -42,10 +96,11 @@ struct Args {
     #[arg(
         long,
         value_name = "NETWORK_URL",
-        help = "Network address of your RPC provider",
+        help = "Network address of your RPC provider. Repeat or comma-separate to send through several.",
+        value_delimiter = ',',
         global = true
     )]
-    rpc: Option<String>,
+    rpc: Vec<String>,
 
     #[clap(
         global = true,
@@ -99,11 +154,110 @@ struct Args {
     #[arg(
         long,
         value_name = "MICROLAMPORTS",
-        help = "Number of microlamports to pay as priority fee per transaction",
+        help = "Number of microlamports to pay as priority fee per transaction, or auto[:PERCENTILE] to follow recent fees",
         default_value = "0",
         global = true
     )]
-    priority_fee: u64,
+    priority_fee: PriorityFee,
+
+    #[arg(
+        long,
+        value_name = "MICROLAMPORTS",
+        help = "Upper bound on the priority fee per transaction",
+        global = true
+    )]
+    max_priority_fee: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "MULTIPLIER[:EVERY_ATTEMPTS]",
+        help = "Re-sign unlanded transactions with the priority fee raised by MULTIPLIER every EVERY_ATTEMPTS sends (default 10), up to --max-priority-fee",
+        requires = "max_priority_fee",
+        global = true
+    )]
+    fee_escalation: Option<FeeEscalation>,
+
+    #[arg(
+        long,
+        value_name = "SOL",
+        help = "Stop sending transactions once this much SOL has been spent on fees",
+        global = true
+    )]
+    max_sol_spend: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "LAMPORTS",
+        help = "Never send a transaction that could cost more than this in fees",
+        global = true
+    )]
+    max_fee_per_tx: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        help = "Safety margin added to simulated compute unit usage",
+        default_value = "10",
+        global = true
+    )]
+    cu_margin: u64,
+
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Sign transactions with durable nonces, rotating through this many nonce accounts per wallet",
+        global = true
+    )]
+    nonce_accounts: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Send transactions over QUIC directly to the current and upcoming leaders, falling back to RPC",
+        global = true
+    )]
+    tpu: bool,
+
+    #[arg(
+        long,
+        value_name = "WEBSOCKET_URL",
+        help = "Websocket address used to follow slots in --tpu mode. Defaults to one derived from the first RPC URL.",
+        global = true
+    )]
+    ws_url: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Submit transactions as bundles with a tip to this block engine JSON-RPC endpoint",
+        requires = "tip_account",
+        global = true
+    )]
+    bundle_url: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "LAMPORTS",
+        help = "Tip paid with each bundle",
+        default_value = "1000",
+        global = true
+    )]
+    tip_lamports: u64,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Account the block engine collects bundle tips on",
+        global = true
+    )]
+    tip_account: Option<Pubkey>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Address lookup table to build v0 transactions with, as made by `ore alt create`",
+        global = true
+    )]
+    lookup_table: Option<Pubkey>,
 
     #[command(subcommand)]
     command: Commands,
@@ -129,6 +283,12 @@ enum Commands {
     #[command(about = "Fetch the treasury account and balance")]
     Treasury(TreasuryArgs),
 
+    #[command(about = "Manage the durable nonce accounts of each wallet")]
+    Nonce(NonceArgs),
+
+    #[command(about = "Manage the address lookup table used for v0 transactions")]
+    Alt(AltArgs),
+
     #[cfg(feature = "admin")]
     #[command(about = "Initialize the program")]
     Initialize(InitializeArgs),
@@ -175,6 +335,133 @@ struct MineArgs {
         default_value = "1"
     )]
     threads: u64,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Serve Prometheus metrics on this address (e.g. 0.0.0.0:9100)"
+    )]
+    metrics_addr: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "STRATEGY",
+        help = "When to submit solutions: always, average-threshold[:RATIO[:RETRIES]], ewma[:ALPHA[:RATIO[:RETRIES]]] or epoch-start[:WINDOW_SECONDS]",
+        default_value = "average-threshold"
+    )]
+    strategy: StrategyKind,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Hold solutions until the next reset when busses are depleted and the reset is this close",
+        default_value = "0"
+    )]
+    hold_near_reset: i64,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Submit immediately, bypassing the strategy, within this many seconds of an epoch reset",
+        default_value = "0"
+    )]
+    fresh_epoch_window: i64,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        help = "How to choose the bus to mine on",
+        default_value = "max-rewards"
+    )]
+    bus_policy: BusPolicy,
+
+    #[arg(
+        long,
+        value_name = "POLICY",
+        help = "When to pay for epoch resets: never, always, random:<odds> or stuck:<seconds>",
+        default_value = "random:20"
+    )]
+    reset_policy: ResetPolicy,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Write the end-of-session report as JSON to this file"
+    )]
+    report: Option<String>,
+
+    #[arg(
+        long,
+        help = "Dry-run against a simulated chain instead of the network"
+    )]
+    simulate: bool,
+
+    #[arg(
+        long,
+        value_name = "MINES_PER_SECOND",
+        help = "Rate at which simulated competing miners drain the busses",
+        default_value = "10"
+    )]
+    simulate_competition: u64,
+
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Stop after this many mining iterations"
+    )]
+    max_iterations: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "RFC3339_TIME",
+        help = "Stop at this time (e.g. 2024-05-01T06:00:00Z)"
+    )]
+    until: Option<DateTime<FixedOffset>>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        help = "Stop after running this long (e.g. 90s, 45m, 8h, 2d)",
+        value_parser = parse_duration
+    )]
+    duration: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "ORE",
+        help = "Stop once the claimable rewards across all wallets reach this amount"
+    )]
+    target_ore: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "ORE",
+        help = "Between iterations, claim rewards for wallets holding more than this amount"
+    )]
+    auto_claim_threshold: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "TOKEN_ACCOUNT_ADDRESS",
+        help = "Token account to receive auto-claimed rewards. Defaults to wallet 1's token account."
+    )]
+    beneficiary: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "WINDOWS",
+        help = "Only mine within these local time windows (e.g. \"mon-fri 22:00-06:00,sat,sun 00:00-24:00\")",
+        conflicts_with = "schedule_file"
+    )]
+    schedule: Option<Schedule>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Read mining windows from a file, one per line"
+    )]
+    schedule_file: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -197,6 +484,78 @@ struct ClaimArgs {
     beneficiary: Option<String>,
 }
 
+#[derive(Parser, Debug)]
+struct NonceArgs {
+    #[command(subcommand)]
+    command: NonceCommands,
+}
+
+#[derive(Subcommand, Debug)]
+enum NonceCommands {
+    #[command(about = "Show each nonce account's stored blockhash, authority and balance")]
+    List,
+
+    #[command(about = "Create any missing nonce accounts")]
+    Create,
+
+    #[command(about = "Advance the stored nonce of an account")]
+    Advance(NonceAddressArgs),
+
+    #[command(about = "Withdraw SOL from a nonce account to its authority")]
+    Withdraw(NonceWithdrawArgs),
+
+    #[command(about = "Withdraw the whole balance of a nonce account, closing it")]
+    Close(NonceAddressArgs),
+}
+
+#[derive(Parser, Debug)]
+struct NonceAddressArgs {
+    #[arg(
+        value_name = "ADDRESS",
+        help = "The nonce account to use. Defaults to all of them."
+    )]
+    address: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct NonceWithdrawArgs {
+    #[arg(
+        value_name = "ADDRESS",
+        help = "The nonce account to withdraw from"
+    )]
+    address: String,
+
+    #[arg(
+        value_name = "AMOUNT",
+        help = "The amount of SOL to withdraw"
+    )]
+    amount: f64,
+}
+
+#[derive(Parser, Debug)]
+struct AltArgs {
+    #[command(subcommand)]
+    command: AltCommands,
+}
+
+#[derive(Subcommand, Debug)]
+enum AltCommands {
+    #[command(about = "Create a lookup table holding the ore accounts and every wallet's proof and token account")]
+    Create,
+
+    #[command(about = "Add any missing addresses to a lookup table")]
+    Extend(AltExtendArgs),
+}
+
+#[derive(Parser, Debug)]
+struct AltExtendArgs {
+    #[arg(
+        value_name = "ADDRESS",
+        help = "The lookup table to extend. Defaults to --lookup-table."
+    )]
+    address: Option<String>,
+}
+
 #[cfg(feature = "admin")]
 #[derive(Parser, Debug)]
 struct InitializeArgs {}
@@ -227,20 +586,66 @@ async fn main() {
         solana_cli_config::Config::default()
     };
 
+    let fees = FeeConfig::from_args(&args);
+
     // Initialize miner.
-    let cluster = args.rpc.unwrap_or(cli_config.json_rpc_url);
-    println!("URL {}", cluster);
+    // The config file's URL may also be a comma-separated list
+    let clusters: Vec<String> = if args.rpc.is_empty() {
+        cli_config.json_rpc_url.split(',').map(|url| url.trim().to_string()).collect()
+    } else {
+        args.rpc
+    };
+    for cluster in clusters.iter() {
+        println!("URL {}", cluster);
+    }
     let default_keypair1 = args.keypair1.unwrap_or(cli_config.keypair_path.clone());
     let default_keypair2 = args.keypair2.unwrap_or("".to_string());
     let default_keypair3 = args.keypair3.unwrap_or("".to_string());
     let default_keypair4 = args.keypair4.unwrap_or("".to_string());
     let default_keypair5 = args.keypair5.unwrap_or("".to_string());
 
-    let rpc_client = RpcClient::new_with_commitment(cluster, CommitmentConfig::confirmed());
+    let websocket_url = args
+        .ws_url
+        .unwrap_or_else(|| solana_cli_config::Config::compute_websocket_url(&clusters[0]));
+    let rpc_pool = RpcPool::new(clusters, CommitmentConfig::confirmed());
+
+    // Send straight to the leaders, if requested
+    let tpu_client = if args.tpu {
+        tpu::connect(rpc_pool.endpoints[0].client.clone(), &websocket_url).await
+    } else {
+        None
+    };
+
+    // Dry-run mining against an in-process chain, if requested
+    let simulator = match &args.command {
+        Commands::Mine(mine_args) if mine_args.simulate => Some(SimulatedChain::new(mine_args.simulate_competition)),
+        _ => None,
+    };
 
+    let bundle_sender = args.bundle_url.map(|bundle_url| {
+        BundleSender::new(bundle_url, args.tip_lamports, args.tip_account.unwrap())
+    });
+
+    // Resolve transaction accounts through the lookup table, if one is given
+    let mut lookup_tables = Vec::new();
+    if let Some(address) = args.lookup_table {
+        match alt::fetch_lookup_table(&rpc_pool, address).await {
+            Ok(table) => lookup_tables.push(table),
+            Err(err) => println!("Failed to fetch lookup table {}, using legacy transactions: {:?}", address, err),
+        }
+    }
+
+    let transport = Transport {
+        rpc_pool,
+        tpu_client,
+        bundle_sender,
+        lookup_tables,
+        nonce_accounts: args.nonce_accounts,
+        simulator,
+    };
     let miner = Arc::new(Miner::new(
-        Arc::new(rpc_client),
-        args.priority_fee,
+        transport,
+        fees,
         Some(default_keypair1),
         Some(default_keypair2),
         Some(default_keypair3),
@@ -267,11 +672,17 @@ async fn main() {
             miner.treasury().await;
         }
         Commands::Mine(args) => {
-            miner.mine(args.threads).await;
+            miner.mine(args).await;
         }
         Commands::Claim(args) => {
             miner.claim(args.beneficiary.clone(), args.amount).await;
         }
+        Commands::Nonce(args) => {
+            miner.nonce(args).await;
+        }
+        Commands::Alt(args) => {
+            miner.alt(args).await;
+        }
         #[cfg(feature = "admin")]
         Commands::Initialize(_) => {
             miner.initialize().await;
@@ -287,24 +698,80 @@ async fn main() {
     }
 }
 
+// How transactions reach the cluster
+struct Transport {
+    rpc_pool: RpcPool,
+    tpu_client: Option<QuicTpuClient>,
+    bundle_sender: Option<BundleSender>,
+    lookup_tables: Vec<AddressLookupTableAccount>,
+    nonce_accounts: Option<u64>,
+    // Dry-run against this chain instead of the cluster
+    simulator: Option<SimulatedChain>,
+}
+
+// What the miner is willing to pay for its transactions
+struct FeeConfig {
+    priority_fee: PriorityFee,
+    max_priority_fee: Option<u64>,
+    fee_escalation: Option<FeeEscalation>,
+    budget: SpendBudget,
+    cu_margin: u64,
+}
+
+impl FeeConfig {
+    fn from_args(args: &Args) -> Self {
+        Self {
+            priority_fee: args.priority_fee,
+            max_priority_fee: args.max_priority_fee,
+            fee_escalation: args.fee_escalation,
+            budget: SpendBudget::new(args.max_sol_spend.map(sol_to_lamports), args.max_fee_per_tx),
+            cu_margin: args.cu_margin,
+        }
+    }
+}
+
 impl Miner {
     pub fn new(
-        rpc_client: Arc<RpcClient>, 
-        priority_fee: u64, 
+        transport: Transport,
+        fees: FeeConfig,
         keypair_filepath1: Option<String>, 
         keypair_filepath2: Option<String>, 
         keypair_filepath3: Option<String>,
         keypair_filepath4: Option<String>,
         keypair_filepath5: Option<String>,
     ) -> Self {
+        let rpc_pool = Arc::new(transport.rpc_pool);
+        let rpc_client = rpc_pool.endpoints[0].client.clone();
+        let chain: Box<dyn Chain> = match transport.simulator {
+            Some(simulator) => Box::new(Mutex::new(simulator)),
+            None => Box::new(RpcChain::new(rpc_pool.clone(), transport.lookup_tables.clone())),
+        };
+        let nonce_manager = transport
+            .nonce_accounts
+            .filter(|count| *count > 0)
+            .map(|count| NonceManager::new(rpc_pool.clone(), count));
         Self {
             rpc_client,
+            rpc_pool,
+            tpu_client: transport.tpu_client,
+            bundle_sender: transport.bundle_sender,
+            lookup_tables: transport.lookup_tables,
             keypair_filepath1,
             keypair_filepath2,
             keypair_filepath3,
             keypair_filepath4,
             keypair_filepath5,
-            priority_fee,
+            priority_fee: fees.priority_fee,
+            max_priority_fee: fees.max_priority_fee,
+            last_priority_fee: AtomicU64::new(0),
+            fee_escalation: fees.fee_escalation,
+            budget: fees.budget,
+            cu_margin: fees.cu_margin,
+            cu_calibration: CuCalibration::default(),
+            nonce_manager,
+            metrics: Arc::new(Metrics::default()),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            chain,
         }
     }
 
@@ -332,4 +799,20 @@ impl Miner {
             None => panic!("No keypair provided"),
         }
     }
+
+    // The wallet's keypair, or None if no keypair file was given for it
+    pub fn configured_signer(&self, keypair_number: u64) -> Option<Keypair> {
+        let keypair_filepath = match keypair_number {
+            1 => &self.keypair_filepath1,
+            2 => &self.keypair_filepath2,
+            3 => &self.keypair_filepath3,
+            4 => &self.keypair_filepath4,
+            5 => &self.keypair_filepath5,
+            _ => return None,
+        };
+        keypair_filepath
+            .as_ref()
+            .filter(|filepath| !filepath.is_empty())
+            .map(|_| self.signer_by_number(keypair_number))
+    }
 }
@@ -0,0 +1,163 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+// Upper bounds (in seconds) of the submit latency histogram buckets
+const SUBMIT_LATENCY_BUCKETS: [f64; 10] = [1.0, 2.0, 5.0, 10.0, 15.0, 20.0, 30.0, 45.0, 60.0, 120.0];
+
+#[derive(Default)]
+pub struct Metrics {
+    pub hashes: AtomicU64,
+    pub solutions: AtomicU64,
+    pub tx_attempts: AtomicU64,
+    pub tx_landed: AtomicU64,
+    pub tx_failed: AtomicU64,
+    pub resets: AtomicU64,
+    gauges: Mutex<Gauges>,
+    submit_latency: Mutex<Histogram>,
+    first_confirmations: Mutex<BTreeMap<String, u64>>,
+}
+
+#[derive(Default)]
+struct Gauges {
+    reward_rate: f64,
+    claimable_rewards: BTreeMap<String, f64>,
+    sol_balance: BTreeMap<String, f64>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; SUBMIT_LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    pub fn set_reward_rate(&self, reward_rate: f64) {
+        self.gauges.lock().unwrap().reward_rate = reward_rate;
+    }
+
+    pub fn set_claimable_rewards(&self, wallet: String, rewards: f64) {
+        self.gauges.lock().unwrap().claimable_rewards.insert(wallet, rewards);
+    }
+
+    pub fn set_sol_balance(&self, wallet: String, balance: f64) {
+        self.gauges.lock().unwrap().sol_balance.insert(wallet, balance);
+    }
+
+    pub fn observe_submit_latency(&self, seconds: f64) {
+        let mut histogram = self.submit_latency.lock().unwrap();
+        for (i, bound) in SUBMIT_LATENCY_BUCKETS.iter().enumerate() {
+            if seconds <= *bound {
+                histogram.buckets[i] += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    // Counts the endpoint that reported a transaction's confirmation before the others
+    pub fn record_first_confirmation(&self, endpoint: &str) {
+        *self.first_confirmations.lock().unwrap().entry(endpoint.to_string()).or_default() += 1;
+    }
+
+    pub fn first_confirmations(&self) -> BTreeMap<String, u64> {
+        self.first_confirmations.lock().unwrap().clone()
+    }
+
+    // Renders all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            ("ore_hashes_total", "Hashes computed", &self.hashes),
+            ("ore_solutions_total", "Valid solutions found", &self.solutions),
+            ("ore_tx_attempts_total", "Transactions sent to the network", &self.tx_attempts),
+            ("ore_tx_landed_total", "Transactions confirmed", &self.tx_landed),
+            ("ore_tx_failed_total", "Transactions that failed or ran out of retries", &self.tx_failed),
+            ("ore_resets_total", "Epoch resets paid for", &self.resets),
+        ];
+        for (name, help, counter) in counters {
+            writeln!(out, "# HELP {} {}", name, help).ok();
+            writeln!(out, "# TYPE {} counter", name).ok();
+            writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed)).ok();
+        }
+
+        {
+            let histogram = self.submit_latency.lock().unwrap();
+            writeln!(out, "# HELP ore_submit_latency_seconds Time from first submit to landing").ok();
+            writeln!(out, "# TYPE ore_submit_latency_seconds histogram").ok();
+            for (i, bound) in SUBMIT_LATENCY_BUCKETS.iter().enumerate() {
+                writeln!(out, "ore_submit_latency_seconds_bucket{{le=\"{}\"}} {}", bound, histogram.buckets[i]).ok();
+            }
+            writeln!(out, "ore_submit_latency_seconds_bucket{{le=\"+Inf\"}} {}", histogram.count).ok();
+            writeln!(out, "ore_submit_latency_seconds_sum {}", histogram.sum).ok();
+            writeln!(out, "ore_submit_latency_seconds_count {}", histogram.count).ok();
+        }
+
+        writeln!(out, "# HELP ore_endpoint_first_confirmations_total Transactions each RPC endpoint reported confirmed first").ok();
+        writeln!(out, "# TYPE ore_endpoint_first_confirmations_total counter").ok();
+        for (endpoint, count) in self.first_confirmations().iter() {
+            writeln!(out, "ore_endpoint_first_confirmations_total{{endpoint=\"{}\"}} {}", endpoint, count).ok();
+        }
+
+        let gauges = self.gauges.lock().unwrap();
+        writeln!(out, "# HELP ore_reward_rate Current reward rate in ORE").ok();
+        writeln!(out, "# TYPE ore_reward_rate gauge").ok();
+        writeln!(out, "ore_reward_rate {}", gauges.reward_rate).ok();
+        writeln!(out, "# HELP ore_claimable_rewards Claimable rewards per wallet in ORE").ok();
+        writeln!(out, "# TYPE ore_claimable_rewards gauge").ok();
+        for (wallet, rewards) in gauges.claimable_rewards.iter() {
+            writeln!(out, "ore_claimable_rewards{{wallet=\"{}\"}} {}", wallet, rewards).ok();
+        }
+        writeln!(out, "# HELP ore_sol_balance SOL balance per wallet").ok();
+        writeln!(out, "# TYPE ore_sol_balance gauge").ok();
+        for (wallet, balance) in gauges.sol_balance.iter() {
+            writeln!(out, "ore_sol_balance{{wallet=\"{}\"}} {}", wallet, balance).ok();
+        }
+        out
+    }
+}
+
+// Serves `GET /metrics` on the given address until the process exits
+pub async fn serve(metrics: Arc<Metrics>, addr: String) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("Failed to bind metrics endpoint on {}: {:?}", addr, err);
+            return;
+        }
+    };
+    println!("Serving metrics on http://{}/metrics", addr);
+    loop {
+        let Ok((mut socket, _)) = listener.accept().await else {
+            continue;
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let n = socket.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let response = if request.starts_with("GET /metrics ") {
+                let body = metrics.render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+            };
+            socket.write_all(response.as_bytes()).await.ok();
+        });
+    }
+}
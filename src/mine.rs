@@ -2,28 +2,43 @@ use crossbeam::thread;
 use std::{
     collections::HashMap,
     io::{stdout, Write},
+    str::FromStr,
     sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex},
-    time::{Instant, Duration},
+    time::{Duration, Instant},
 };
-use rand::Rng;
-use ore::{self, state::Bus, BUS_ADDRESSES, BUS_COUNT, EPOCH_DURATION};
+use ore::{self, BUS_ADDRESSES, EPOCH_DURATION, TREASURY_ADDRESS};
 use solana_program::{keccak::HASH_BYTES, program_memory::sol_memcmp, pubkey::Pubkey};
 use solana_sdk::{
     instruction::Instruction,
     compute_budget::ComputeBudgetInstruction,
+    native_token::lamports_to_sol,
     keccak::{hashv, Hash as KeccakHash},
     signature::Signer,
-    signer::keypair::Keypair
+    signer::keypair::Keypair,
+    transaction::TransactionError,
 };
 
 use crate::{
-    cu_limits::{CU_LIMIT_MINE, CU_LIMIT_RESET},
-    utils::{get_clock_account, get_proof, get_treasury},
-    Miner,
+    budget::estimate_fee,
+    busses::{BusSelection, BusSelector},
+    cu_limits::{mine_cu_limit, CU_LIMIT_RESET},
+    epoch::EpochTiming,
+    fleet::{Fleet, Solution},
+    limits::RunLimits,
+    schedule::Schedule,
+    metrics,
+    strategy::{Decision, TreasurySnapshot},
+    Miner, MineArgs,
 };
 
-// Odds of being selected to submit a reset tx
-const RESET_ODDS: u64 = 20;
+// Only use busses holding at least this many times the reward rate
+const BUS_MIN_REWARDS_MULTIPLE: u64 = 20;
+
+// How often to check whether a paused schedule has reopened
+const SCHEDULE_POLL_SECONDS: u64 = 5;
+
+// Pause before resubmitting after a batch failed to land
+const SUBMIT_RETRY_DELAY: Duration = Duration::from_secs(2);
 
 /*
 struct SharedNextHashRangeData {
@@ -39,7 +54,9 @@ struct SharedNextHashRangeData {
 const WALLETS: u64 = 5;
 
 impl Miner {
-    pub async fn mine(&self, threads: u64) {
+    pub async fn mine(&self, args: MineArgs) {
+        let threads = args.threads;
+
         // Register, if needed.
         let signer = self.signer();
         
@@ -47,27 +64,115 @@ impl Miner {
             self.register_by_number(wallet).await;    
         }        
 
+        // Expose metrics for long-running miners
+        if let Some(metrics_addr) = args.metrics_addr.clone() {
+            tokio::spawn(metrics::serve(self.metrics.clone(), metrics_addr));
+        }
+
+        // Stop hashing on Ctrl-C, but let in-flight submissions finish
+        let shutdown = self.shutdown.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                println!("\nShutting down after in-flight submissions, press Ctrl-C again to exit now...");
+                shutdown.store(true, Ordering::Relaxed);
+            }
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(1);
+            }
+        });
+
+        // Resolve where auto-claimed rewards go
+        let auto_claim = match args.auto_claim_threshold {
+            Some(threshold) => {
+                let beneficiary = match &args.beneficiary {
+                    Some(beneficiary) => Pubkey::from_str(beneficiary).expect("Failed to parse beneficiary address"),
+                    None => self.initialize_ata(&signer).await,
+                };
+                Some(((threshold * 10f64.powf(ore::TOKEN_DECIMALS as f64)) as u64, beneficiary))
+            }
+            None => None,
+        };
+
+        let schedule = match &args.schedule_file {
+            Some(path) => Some(Schedule::from_file(path).unwrap_or_else(|err| {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            })),
+            None => args.schedule.clone(),
+        };
+
         let mut stdout = stdout();
-        let mut rng = rand::thread_rng();
+        let mut session = self.start_session().await;
 
-        let mut reward_rate_sum = 0 as f64;
-        let mut reward_rate_count = 0;
-        let mut reward_rate_retries = 0;
-        let mut last_reward_rate = 0 as f64;
+        let mut fleet = Fleet::new((1..WALLETS + 1).collect());
+        let limits = RunLimits::new(
+            args.max_iterations,
+            args.until,
+            args.duration,
+            args.target_ore,
+        );
+        let mut strategy = args.strategy.build();
+        let mut bus_selector = BusSelector::new(args.bus_policy);
         let mut last_submit_time = 0;
         let mut total_times_mined = 0;
         let mut total_mining_mills = 0;
         let mut total_submit_mills = 0;
+        let mut total_resets = 0;
 
         // Start mining loop
-        loop {
+        'mine: loop {
+            if self.shutdown.load(Ordering::Relaxed) {
+                break;
+            }
+            if self.budget.exhausted() {
+                println!("SOL spend budget used up, stopping");
+                break;
+            }
+            if let Some(reason) = limits.reached(session.iterations()) {
+                println!("{}, stopping", reason);
+                break;
+            }
+            if limits.target_ore.is_some() {
+                let mut claimable_rewards = 0;
+                for wallet in 1..WALLETS + 1 {
+                    if let Ok(proof) = self.fetch_proof(self.signer_by_number(wallet).pubkey()).await {
+                        claimable_rewards += proof.claimable_rewards;
+                    }
+                }
+                let claimable_ore = (claimable_rewards as f64) / (10f64.powf(ore::TOKEN_DECIMALS as f64));
+                if let Some(reason) = limits.target_reached(claimable_ore) {
+                    println!("{}, stopping", reason);
+                    break;
+                }
+            }
+            if let Some(schedule) = &schedule {
+                if !self.wait_for_schedule(schedule).await {
+                    break;
+                }
+            }
+            fleet.release_expired(self.chain.now());
+
             // Fetch account state
-            let treasury = get_treasury(&self.rpc_client).await;
+            let treasury = self.fetch_treasury().await;
             let reward_rate = (treasury.reward_rate as f64) / (10f64.powf(ore::TOKEN_DECIMALS as f64));
-            let priority_fee = self.priority_fee;
+            let fee_accounts: Vec<Pubkey> = [ore::ID, TREASURY_ADDRESS]
+                .into_iter()
+                .chain(BUS_ADDRESSES)
+                .collect();
+            let priority_fee = self.get_priority_fee(&fee_accounts).await;
+            self.metrics.set_reward_rate(reward_rate);
+            session.record_reward_rate(reward_rate);
 
             stdout.write_all(b"\x1b[2J\x1b[3J\x1b[H").ok();
             
+            let clock = self.fetch_clock().await;
+            let epoch = EpochTiming::new(&treasury, &clock);
+            if epoch.needs_reset() {
+                println!("Epoch reset is due\n");
+            } else {
+                println!("Next epoch reset in {} seconds\n", epoch.seconds_to_reset());
+            }
+
             if last_submit_time > 0 {
                 println!("Last reward took {} seconds to land\n", last_submit_time/1000);
             }
@@ -76,30 +181,17 @@ impl Miner {
 
             println!("Current reward rate: {} ORE", reward_rate);
             println!("Using priority fee: {} micro-lamports", priority_fee);
-            println!("Avg reward rate: {} ORE", reward_rate_sum as f64 / reward_rate_count as f64);
+            if let Some(summary) = strategy.summary() {
+                println!("{}", summary);
+            }
             if total_times_mined > 0 {
                 println!("Total times mined: {}", total_times_mined);
                 println!("Avg time per mine: {} seconds", (total_submit_mills+total_mining_mills) / total_times_mined / 1000);
+                println!("Resets paid for: {}", total_resets);
             }
-           
-            //don't count same rate repeating
-            if last_reward_rate as f64 != reward_rate {
-                last_reward_rate = reward_rate;
-                reward_rate_sum += reward_rate;
-                reward_rate_count += 1;
-            }
-
-            //if reward less than average, retry a few times
-            if reward_rate < (reward_rate_sum as f64 / reward_rate_count as f64) * 0.875 {
-                println!("Current reward rate less than average, waiting a few more seconds...");
-                if reward_rate_retries < 3 {
-                    reward_rate_retries += 1;
-                    std::thread::sleep(Duration::from_millis(3000));
-                    continue;
-                }
-                else {
-                    reward_rate_retries = 0;
-                }
+            let quarantined = fleet.quarantined();
+            if !quarantined.is_empty() {
+                println!("Quarantined wallets: {:?}", quarantined);
             }
 
             // test for mine speed
@@ -111,127 +203,362 @@ impl Miner {
             } 
             */
 
-            // Escape sequence that clears the screen and the scrollback buffer
+            // Only re-mine wallets whose last solution landed or that have none yet
             println!("\nMining for valid hashes...");
-            let mut next_hashes: HashMap<u64, KeccakHash> = HashMap::new();
-            let mut nonces: HashMap<u64, u64> = HashMap::new();
-
             let mut total_mine_time = 0;
-            for wallet in 1..WALLETS+1 {
-                let proof = get_proof(&self.rpc_client, self.signer_by_number(wallet).pubkey()).await;
+            for wallet in fleet.stale() {
+                let pubkey = self.signer_by_number(wallet).pubkey();
+                let proof = match self.fetch_proof(pubkey).await {
+                    Ok(proof) => proof,
+                    Err(err) => {
+                        fleet.record_error(wallet, &format!("{:?}", err), self.chain.now());
+                        continue;
+                    }
+                };
                 //println!("Proof Hash {} : {}", wallet, proof.hash.to_string());
                 let rewards = (proof.claimable_rewards as f64) / (10f64.powf(ore::TOKEN_DECIMALS as f64));
                 println!("Wallet {} claimable rewards: {} ORE", wallet, rewards);
+                self.metrics.set_claimable_rewards(pubkey.to_string(), rewards);
+                if args.metrics_addr.is_some() {
+                    if let Ok(lamports) = self.fetch_balance(&pubkey).await {
+                        self.metrics.set_sol_balance(pubkey.to_string(), lamports_to_sol(lamports));
+                    }
+                }
                 let start_time = Instant::now();     
-                let (next_hash, nonce) = self.find_next_hash_par(&self.signer_by_number(wallet), proof.hash.into(), treasury.difficulty.into(), threads);
+                let Some((next_hash, nonce)) = self.find_next_hash_par(&self.signer_by_number(wallet), proof.hash.into(), treasury.difficulty.into(), threads, schedule.as_ref()) else {
+                    if self.shutdown.load(Ordering::Relaxed) {
+                        break 'mine;
+                    }
+                    // The schedule closed mid-hash, wait for it at the top of the loop
+                    continue 'mine;
+                };
                 total_mine_time += start_time.elapsed().as_millis();
+                self.metrics.solutions.fetch_add(1, Ordering::Relaxed);
                 //println!("Next Hash {} : {}", wallet, next_hash.to_string());
-                next_hashes.insert(wallet, next_hash);
-                nonces.insert(wallet, nonce);
+                if let Some(state) = fleet.get_mut(wallet) {
+                    state.solution = Some(Solution { hash: next_hash, nonce });
+                }
             }
             total_times_mined += 1;
             total_mining_mills += total_mine_time;
             println!("This hash mining time: {} seconds", total_mine_time/1000);
             println!("Avg hash mining time: {} seconds", total_mining_mills/total_times_mined/1000);
 
+            if fleet.ready().is_empty() {
+                println!("No healthy wallets with solutions, retrying shortly...");
+                self.sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+
+            // Hold submission until the epoch and the strategy are favorable
+            loop {
+                if self.shutdown.load(Ordering::Relaxed) {
+                    break 'mine;
+                }
+                if let Some(reason) = limits.reached(session.iterations()) {
+                    println!("{}, stopping", reason);
+                    break 'mine;
+                }
+                if let Some(schedule) = &schedule {
+                    if !self.wait_for_schedule(schedule).await {
+                        break 'mine;
+                    }
+                }
+                let treasury = self.fetch_treasury().await;
+                let clock = self.fetch_clock().await;
+                let epoch = EpochTiming::new(&treasury, &clock);
+
+                // Warn if the busses can't cover the whole fleet before the next reset
+                if !epoch.needs_reset() {
+                    if let Ok(busses) = self.get_busses().await {
+                        let total_rewards: u64 = busses.iter().map(|bus| bus.rewards).sum();
+                        let needed = treasury.reward_rate.saturating_mul(fleet.ready().len() as u64);
+                        if total_rewards < needed {
+                            println!(
+                                "Warning: busses are nearly depleted ({} ORE left across all busses), next reset in {} seconds",
+                                (total_rewards as f64) / (10f64.powf(ore::TOKEN_DECIMALS as f64)),
+                                epoch.seconds_to_reset()
+                            );
+                            if epoch.seconds_to_reset() <= args.hold_near_reset {
+                                println!("Holding solutions until the busses are refilled...");
+                                self.sleep(Duration::from_secs(epoch.seconds_to_reset() as u64 + 1)).await;
+                                continue;
+                            }
+                        }
+                    }
+                }
+
+                // Submit right away while the busses are freshly refilled
+                if epoch.is_fresh(args.fresh_epoch_window) {
+                    println!("Epoch started {} seconds ago, submitting immediately", epoch.elapsed());
+                    break;
+                }
+
+                match strategy.decide(&TreasurySnapshot::new(&treasury, clock.unix_timestamp)) {
+                    Decision::Submit => break,
+                    Decision::Wait { delay, reason } => {
+                        println!("{}", reason);
+                        self.sleep(delay).await;
+                    }
+                }
+            }
+
             // Submit mine tx.
-            // Use busses randomly so on each epoch, transactions don't pile on the same busses
             //println!("\n\nSubmitting hash for validation...");
-             let start_time_submit = Instant::now();                 
+            let start_time_submit = Instant::now();                 
+            let mut landed = false;
             'submit: loop {
-                // Double check we're submitting for the right challenge
-                for wallet in 1..WALLETS+1 {
+                // Stop on time even while a submission keeps failing
+                if let Some(reason) = limits.reached(session.iterations()) {
+                    println!("{}, stopping", reason);
+                    break 'mine;
+                }
+
+                // Double check we're submitting for the right challenge, and leave out
+                // wallets whose solution couldn't be checked this round
+                let mut unchecked: Vec<u64> = Vec::new();
+                for wallet in fleet.ready() {
                     //println!("\nChecking hash already validated for wallet {}...", wallet);
-                    let proof_ = get_proof(&self.rpc_client, self.signer_by_number(wallet).pubkey()).await;
+                    let pubkey = self.signer_by_number(wallet).pubkey();
+                    let proof_ = match self.fetch_proof(pubkey).await {
+                        Ok(proof_) => proof_,
+                        Err(err) => {
+                            fleet.record_error(wallet, &format!("{:?}", err), self.chain.now());
+                            unchecked.push(wallet);
+                            continue;
+                        }
+                    };
+                    let Some(state) = fleet.get_mut(wallet) else {
+                        continue;
+                    };
+                    let Some(solution) = state.solution else {
+                        continue;
+                    };
                     if !self.validate_hash(
-                        *next_hashes.get(&wallet).unwrap(),
+                        solution.hash,
                         proof_.hash.into(),
-                        self.signer_by_number(wallet).pubkey(),
-                        *nonces.get(&wallet).unwrap(),
+                        pubkey,
+                        solution.nonce,
                         treasury.difficulty.into(),
                     ) {
-                        println!("{} Success: Hash already validated for wallet {}! An earlier transaction must have landed.", wallet, chrono::offset::Local::now());
+                        println!("{} Success: Hash already validated for wallet {}! An earlier transaction must have landed.", chrono::offset::Local::now(), wallet);
+                        state.solution = None;
+                        landed = true;
+                    }
+                }
+
+                // Only healthy wallets with a valid solution are submitted
+                let batch: Vec<u64> = fleet
+                    .ready()
+                    .into_iter()
+                    .filter(|wallet| !unchecked.contains(wallet))
+                    .collect();
+                if batch.is_empty() {
+                    if fleet.ready().is_empty() {
                         break 'submit;
                     }
+                    // Check the remaining wallets again shortly
+                    self.sleep(Duration::from_millis(1000)).await;
+                    continue;
                 }
 
                 // Reset epoch, if needed
-                let treasury = get_treasury(&self.rpc_client).await;
-                let clock = get_clock_account(&self.rpc_client).await;
+                let treasury = self.fetch_treasury().await;
+                let clock = self.fetch_clock().await;
                 let threshold = treasury.last_reset_at.saturating_add(EPOCH_DURATION);
-                if clock.unix_timestamp.ge(&threshold) {
-                    // There are a lot of miners right now, so randomly select into submitting tx
-                    if rng.gen_range(0..RESET_ODDS).eq(&0) {
-                        println!("Sending epoch reset transaction...");
+                if clock.unix_timestamp.ge(&threshold)
+                    && args.reset_policy.should_reset(clock.unix_timestamp.saturating_sub(threshold))
+                {
+                    // Make sure nobody beat us to it before paying for the reset
+                    let latest_treasury = self.fetch_treasury().await;
+                    if latest_treasury.last_reset_at != treasury.last_reset_at {
+                        println!("Epoch was already reset by another miner");
+                    } else {
+                        println!("Sending epoch reset transaction with priority fee {} micro-lamports...", priority_fee);
                         let cu_limit_ix =
                             ComputeBudgetInstruction::set_compute_unit_limit(CU_LIMIT_RESET);
                         let cu_price_ix =
                             ComputeBudgetInstruction::set_compute_unit_price(priority_fee);
                         let reset_ix = ore::instruction::reset(signer.pubkey());
-                        self.send_and_confirm(&[cu_limit_ix, cu_price_ix, reset_ix], false, true, vec![&signer])
+                        if self
+                            .send_and_confirm(&[cu_limit_ix, cu_price_ix, reset_ix], true, false, vec![&signer])
                             .await
-                            .ok();
+                            .is_ok()
+                        {
+                            total_resets += 1;
+                            self.metrics.resets.fetch_add(1, Ordering::Relaxed);
+                        }
                     }
                 }
 
                 // Submit request.
-                let bus = self.find_bus_id(treasury.reward_rate).await;
-                let bus_rewards = (bus.rewards as f64) / (10f64.powf(ore::TOKEN_DECIMALS as f64));
-                println!("\nSending on bus {} ({} ORE)", bus.id, bus_rewards);
-                let cu_limit_ix = ComputeBudgetInstruction::set_compute_unit_limit(CU_LIMIT_MINE);
-                let cu_price_ix =
-                    ComputeBudgetInstruction::set_compute_unit_price(priority_fee);
-                
-                let mut mine_ixs: Vec<Instruction> = Vec::new();
-                mine_ixs.push(cu_limit_ix);
-                mine_ixs.push(cu_price_ix);
-                for wallet in 1..WALLETS+1 {
+                let busses = match self.get_busses().await {
+                    Ok(busses) => busses,
+                    Err(err) => {
+                        println!("Error fetching busses: {:?}", err);
+                        self.sleep(Duration::from_millis(1000)).await;
+                        continue;
+                    }
+                };
+                let min_rewards = treasury.reward_rate.saturating_mul(BUS_MIN_REWARDS_MULTIPLE);
+                let assigned_busses = match bus_selector.select(&busses, batch.len(), treasury.reward_rate, min_rewards) {
+                    BusSelection::Assigned(assigned_busses) => assigned_busses,
+                    BusSelection::Depleted => {
+                        let wait = threshold.saturating_sub(clock.unix_timestamp).max(0) as u64 + 1;
+                        println!("All busses depleted, waiting {} seconds for the next epoch reset...", wait);
+                        self.sleep(Duration::from_secs(wait)).await;
+                        continue;
+                    }
+                };
+                println!();
+                for (wallet, bus) in batch.iter().zip(assigned_busses.iter()) {
+                    let bus_rewards = (bus.rewards as f64) / (10f64.powf(ore::TOKEN_DECIMALS as f64));
+                    println!("Sending wallet {} on bus {} ({} ORE)", wallet, bus.id, bus_rewards);
+                }
+                println!("Submitting with priority fee {} micro-lamports", priority_fee);
+                let mut items: Vec<(u64, Instruction)> = Vec::new();
+                for (wallet, bus) in batch.iter().zip(assigned_busses.iter()) {
+                    let solution = fleet.get_mut(*wallet).and_then(|state| state.solution).unwrap();
                     let ix_mine = ore::instruction::mine(
-                        self.signer_by_number(wallet).pubkey(),
+                        self.signer_by_number(*wallet).pubkey(),
                         BUS_ADDRESSES[bus.id as usize],
-                        (*next_hashes.get(&wallet).unwrap()).into(),
-                        *nonces.get(&wallet).unwrap(),
+                        solution.hash.into(),
+                        solution.nonce,
                     );
-                    mine_ixs.push(ix_mine);
+                    items.push((*wallet, ix_mine));
                     //println!("Added mine txn for wallet {}", wallet);
                 }
+                let bus_by_wallet: HashMap<u64, u64> = batch
+                    .iter()
+                    .zip(assigned_busses.iter())
+                    .map(|(wallet, bus)| (*wallet, bus.id))
+                    .collect();
+
+                // Each transaction is paid for by the first wallet in it
+                let mut tx_batches = self.pack_batches(items, None, |mines| {
+                    vec![
+                        ComputeBudgetInstruction::set_compute_unit_limit(mine_cu_limit(mines)),
+                        ComputeBudgetInstruction::set_compute_unit_price(priority_fee),
+                    ]
+                });
 
-                //this is ugly but keypair doesn't implement clone() :(
-                let signer1 = self.signer_by_number(1);
-                let signer2 = self.signer_by_number(2);
-                let signer3 = self.signer_by_number(3);
-                let signer4 = self.signer_by_number(4);
-                let signer5 = self.signer_by_number(5);
+                // Size the compute budgets now, so the fee limits below see what is sent
+                for tx_batch in tx_batches.iter_mut() {
+                    let keypairs = self.batch_keypairs(tx_batch);
+                    let signers: Vec<&Keypair> = keypairs.iter().collect();
+                    tx_batch.ixs = self.calibrate_cu_limit(&tx_batch.ixs, &signers).await;
+                }
 
-                let signers = vec![&signer1, &signer2, &signer3, &signer4, &signer5];
+                // Don't keep retrying transactions the fee limits won't allow. Each batch
+                // is its own transaction, so only their total counts against the session.
+                let fees: Vec<u64> = tx_batches
+                    .iter()
+                    .map(|tx_batch| estimate_fee(&tx_batch.ixs, tx_batch.signers.len()))
+                    .collect();
+                let within_limits = fees
+                    .iter()
+                    .try_for_each(|fee| self.budget.check_per_tx(*fee))
+                    .and_then(|_| self.budget.check_remaining(fees.iter().sum()));
+                if let Err(reason) = within_limits {
+                    println!("{}, stopping", reason);
+                    break 'mine;
+                }
 
-                match self
-                    .send_and_confirm(&mine_ixs.into_boxed_slice(), false, false, signers)
-                    .await
-                {
-                    Ok(sig) => {
-                        println!("{} Success: {}", chrono::offset::Local::now(), sig);
-                        break;
+                let outcomes = self.send_batches(&tx_batches, false).await;
+                for (tx_batch, outcome) in tx_batches.iter().zip(outcomes.iter()) {
+                    match &outcome.result {
+                        Ok(sig) => {
+                            println!("{} Success: {}", chrono::offset::Local::now(), sig);
+                            for wallet in tx_batch.wallets.iter() {
+                                println!("Wallet {} credited by bus {}", wallet, bus_by_wallet[wallet]);
+                                fleet.record_success(*wallet);
+                                if let Some(state) = fleet.get_mut(*wallet) {
+                                    state.solution = None;
+                                }
+                            }
+                            landed = true;
+                        }
+                        Err(err) => {
+                            // Find the wallet whose instruction sinks the shared transaction
+                            let keypairs = self.batch_keypairs(tx_batch);
+                            let signers: Vec<&Keypair> = keypairs.iter().collect();
+                            let failing_wallet = self
+                                .find_failing_instruction(&tx_batch.ixs, &signers)
+                                .await
+                                .and_then(|idx| idx.checked_sub(tx_batch.offset))
+                                .and_then(|i| tx_batch.wallets.get(i));
+                            match failing_wallet {
+                                Some(wallet) => fleet.record_error(*wallet, "mine instruction failed", self.chain.now()),
+                                // No instruction to blame, so it's on whoever pays for the transaction
+                                None => fleet.record_error(tx_batch.signers[0], &format!("{:?}", err), self.chain.now()),
+                            }
+                        }
                     }
-                    Err(_err) => {
-                        // TODO
+                }
+                if outcomes.iter().all(|outcome| outcome.result.is_ok()) {
+                    break;
+                }
+                if self.shutdown.load(Ordering::Relaxed) {
+                    println!("Submission did not land before shutdown");
+                    break 'mine;
+                }
+                self.sleep(SUBMIT_RETRY_DELAY).await;
+            }
+            if landed {
+                last_submit_time = start_time_submit.elapsed().as_millis();
+                total_submit_mills += last_submit_time;
+                self.metrics.observe_submit_latency(last_submit_time as f64 / 1000.0);
+                session.record_land(last_submit_time);
+            }
+            session.record_iteration();
+
+            // Claim rewards for wallets over the threshold before the next round of hashing
+            if let Some((threshold, beneficiary)) = auto_claim {
+                let batch = self.build_claims(beneficiary, None, threshold).await;
+                if !batch.wallets.is_empty() {
+                    let amount = (batch.amount as f64) / (10f64.powf(ore::TOKEN_DECIMALS as f64));
+                    println!("Auto-claiming {} ORE from wallets {:?}...", amount, batch.wallets);
+                    for outcome in self.send_claims(&batch).await {
+                        match outcome.result {
+                            Ok(sig) => println!("{} Ore Claimed Successfully! to {} : {}", batch.amount_for(&outcome.wallets), beneficiary, sig),
+                            Err(err) => println!("Auto-claim failed for wallets {:?}: {:?}", outcome.wallets, err),
+                        }
                     }
                 }
             }
-            last_submit_time = start_time_submit.elapsed().as_millis();
-            total_submit_mills += last_submit_time;
         }
+
+        self.finish_session(session, args.report.clone()).await;
     }
 
-    async fn find_bus_id(&self, reward_rate: u64) -> Bus {
-        let mut rng = rand::thread_rng();
-        loop {
-            let bus_id = rng.gen_range(0..BUS_COUNT);
-            if let Ok(bus) = self.get_bus(bus_id).await {
-                if bus.rewards.gt(&reward_rate.saturating_mul(20)) {
-                    return bus;
+    // Pauses while outside the mining schedule. Returns false if shut down while waiting.
+    async fn wait_for_schedule(&self, schedule: &Schedule) -> bool {
+        let mut announced = false;
+        while !schedule.is_open(chrono::Local::now()) {
+            if self.shutdown.load(Ordering::Relaxed) {
+                return false;
+            }
+            if !announced {
+                match schedule.next_open(chrono::Local::now()) {
+                    Some(next) => println!("Outside mining schedule, pausing until {}...", next.format("%a %H:%M")),
+                    None => println!("Outside mining schedule, pausing..."),
                 }
+                announced = true;
             }
+            // The schedule follows the wall clock, even when simulating
+            tokio::time::sleep(Duration::from_secs(SCHEDULE_POLL_SECONDS)).await;
+        }
+        if announced {
+            println!("Mining schedule window opened, resuming");
+        }
+        true
+    }
+
+    // Simulates a transaction to find the index of the instruction that makes it fail
+    async fn find_failing_instruction(&self, ixs: &[Instruction], signers: &[&Keypair]) -> Option<usize> {
+        match self.chain.simulate(ixs, signers).await.ok()?.err {
+            Some(TransactionError::InstructionError(idx, _)) => Some(idx as usize),
+            _ => None,
         }
     }
 
@@ -261,7 +588,8 @@ impl Miner {
         hash: KeccakHash,
         difficulty: KeccakHash,
         threads: u64,
-    ) -> (KeccakHash, u64) {
+        schedule: Option<&Schedule>,
+    ) -> Option<(KeccakHash, u64)> {
         let found_solution = Arc::new(AtomicBool::new(false));
         let solution = Arc::new(Mutex::new((KeccakHash::new_from_array([0; 32]), 0)));
         let pubkey = signer.pubkey();
@@ -273,11 +601,19 @@ impl Miner {
                 let solution = Arc::clone(&solution);
                 let start_nonce = t * work_per_thread;
                 let end_nonce = start_nonce + work_per_thread;
+                let metrics = self.metrics.clone();
+                let shutdown = self.shutdown.clone();
                 s.spawn(move |_| {
+                    let mut hashes = 0;
                     for nonce in start_nonce..end_nonce {
-                        if nonce % 100_000 == 0 && found_solution.load(Ordering::Relaxed) {
+                        if nonce % 100_000 == 0
+                            && (found_solution.load(Ordering::Relaxed)
+                                || shutdown.load(Ordering::Relaxed)
+                                || schedule.is_some_and(|schedule| !schedule.is_open(chrono::Local::now())))
+                        {
                             break;
                         }
+                        hashes += 1;
                         let next_hash = hashv(&[
                             hash.as_ref(),
                             pubkey.as_ref(),
@@ -290,12 +626,17 @@ impl Miner {
                             break;
                         }
                     }
+                    metrics.hashes.fetch_add(hashes, Ordering::Relaxed);
                 });
             }
         }).unwrap();
     
+        // Interrupted by shutdown or the schedule closing before a solution was found
+        if !found_solution.load(Ordering::Relaxed) {
+            return None;
+        }
         let r_solution = solution.lock().expect("Failed to get lock");
-        *r_solution
+        Some(*r_solution)
     }
 
     pub fn validate_hash(
@@ -328,8 +669,6 @@ impl Miner {
     }
 
     pub async fn get_ore_display_balance(&self, signer_number: u64) -> String {
-        let client = self.rpc_client.clone();
-        
         let signer = self.signer_by_number(signer_number);
 
         let token_account_address = spl_associated_token_account::get_associated_token_address(
@@ -337,15 +676,9 @@ impl Miner {
             &ore::MINT_ADDRESS,
         );
 
-        match client.get_token_account(&token_account_address).await {
-            Ok(token_account) => {
-                if let Some(token_account) = token_account {
-                    token_account.token_amount.ui_amount_string
-                } else {
-                    "0.00".to_string()
-                }
-            }
-            Err(_) => "0.00".to_string(),
+        match self.chain.token_balance(token_account_address).await {
+            Ok(Some(amount)) => spl_token::amount_to_ui_amount_string(amount, ore::TOKEN_DECIMALS),
+            _ => "0.00".to_string(),
         }
     }
 }
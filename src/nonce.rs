@@ -0,0 +1,289 @@
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    nonce_utils,
+};
+use solana_program::{
+    hash::Hash,
+    instruction::Instruction,
+    native_token::{lamports_to_sol, sol_to_lamports},
+    nonce::state::{Data, State},
+    pubkey::Pubkey,
+    system_instruction, system_program,
+};
+use solana_sdk::{
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+
+use crate::{rpc_pool::RpcPool, Miner, NonceArgs, NonceCommands, WALLETS};
+
+const NONCE_SEED_PREFIX: &str = "ore-nonce";
+
+// Nonce accounts per wallet managed by the `nonce` command when none are configured
+const DEFAULT_NONCE_ACCOUNTS: u64 = 1;
+
+// A pool of durable nonce accounts per authority. The accounts are derived from the
+// authority with `create_account_with_seed`, so only the authority can create them.
+pub struct NonceManager {
+    pub rpc_pool: Arc<RpcPool>,
+    pub capacity: u64,
+    idx: AtomicU64,
+    // Authorities whose nonce accounts are known to exist
+    ready: Mutex<HashSet<Pubkey>>,
+}
+
+impl NonceManager {
+    pub fn new(rpc_pool: Arc<RpcPool>, capacity: u64) -> Self {
+        NonceManager {
+            rpc_pool,
+            capacity,
+            idx: AtomicU64::new(0),
+            ready: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn seed(idx: u64) -> String {
+        format!("{}-{}", NONCE_SEED_PREFIX, idx)
+    }
+
+    pub fn address(authority: &Pubkey, idx: u64) -> Pubkey {
+        Pubkey::create_with_seed(authority, &Self::seed(idx), &system_program::id()).unwrap()
+    }
+
+    pub fn addresses(&self, authority: &Pubkey) -> Vec<Pubkey> {
+        (0..self.capacity).map(|idx| Self::address(authority, idx)).collect()
+    }
+
+    // Creates any missing nonce accounts for the payer and waits until they are confirmed
+    pub async fn ensure_accounts(&self, payer: &Keypair) -> ClientResult<()> {
+        let authority = payer.pubkey();
+        if self.ready.lock().unwrap().contains(&authority) {
+            return Ok(());
+        }
+        for idx in 0..self.capacity {
+            self.create(payer, idx).await?;
+        }
+        self.ready.lock().unwrap().insert(authority);
+        Ok(())
+    }
+
+    // Creates the nonce account at `idx`, unless it already exists
+    pub async fn create(&self, payer: &Keypair, idx: u64) -> ClientResult<Option<Pubkey>> {
+        let authority = payer.pubkey();
+        let nonce_pubkey = Self::address(&authority, idx);
+        if self.fetch(&nonce_pubkey).await.is_ok() {
+            return Ok(None);
+        }
+        let rent = self
+            .rpc_pool
+            .read(|client| async move { client.get_minimum_balance_for_rent_exemption(State::size()).await })
+            .await?;
+        let ixs = system_instruction::create_nonce_account_with_seed(
+            &authority,
+            &nonce_pubkey,
+            &authority,
+            &Self::seed(idx),
+            &authority,
+            rent,
+        );
+        println!("Creating nonce account {}...", nonce_pubkey);
+        self.send(&ixs, payer).await?;
+        Ok(Some(nonce_pubkey))
+    }
+
+    pub async fn advance(&self, authority: &Keypair, nonce_pubkey: &Pubkey) -> ClientResult<Signature> {
+        let ix = system_instruction::advance_nonce_account(nonce_pubkey, &authority.pubkey());
+        self.send(&[ix], authority).await
+    }
+
+    // Withdraws lamports back to the authority. Withdrawing the whole balance closes the account.
+    pub async fn withdraw(&self, authority: &Keypair, nonce_pubkey: &Pubkey, lamports: u64) -> ClientResult<Signature> {
+        let ix = system_instruction::withdraw_nonce_account(
+            nonce_pubkey,
+            &authority.pubkey(),
+            &authority.pubkey(),
+            lamports,
+        );
+        self.send(&[ix], authority).await
+    }
+
+    async fn send(&self, ixs: &[Instruction], payer: &Keypair) -> ClientResult<Signature> {
+        let blockhash = self
+            .rpc_pool
+            .read(|client| async move { client.get_latest_blockhash().await })
+            .await?;
+        let tx = &Transaction::new_signed_with_payer(ixs, Some(&payer.pubkey()), &[payer], blockhash);
+        self.rpc_pool
+            .read(|client| async move { client.send_and_confirm_transaction(tx).await })
+            .await
+    }
+
+    // The stored state of a nonce account
+    pub async fn fetch(&self, nonce_pubkey: &Pubkey) -> ClientResult<Data> {
+        let account = self
+            .rpc_pool
+            .read(|client| async move { client.get_account_with_commitment(nonce_pubkey, client.commitment()).await })
+            .await?
+            .value
+            .ok_or_else(|| ClientError {
+                request: None,
+                kind: ClientErrorKind::Custom(format!("Nonce account {} not found", nonce_pubkey)),
+            })?;
+        nonce_utils::data_from_account(&account).map_err(nonce_error)
+    }
+
+    // The next nonce account in the rotation and the blockhash stored in it, so several
+    // transactions can be in flight at once
+    pub async fn next(&self, authority: &Pubkey) -> ClientResult<(Pubkey, Hash)> {
+        let idx = self.idx.fetch_add(1, Ordering::Relaxed) % self.capacity;
+        let nonce_pubkey = Self::address(authority, idx);
+        let data = self.fetch(&nonce_pubkey).await?;
+        Ok((nonce_pubkey, data.blockhash()))
+    }
+}
+
+fn nonce_error(err: nonce_utils::Error) -> ClientError {
+    ClientError {
+        request: None,
+        kind: ClientErrorKind::Custom(format!("Nonce account error: {}", err)),
+    }
+}
+
+impl Miner {
+    pub async fn nonce(&self, args: NonceArgs) {
+        let default_manager;
+        let nonce_manager = match &self.nonce_manager {
+            Some(nonce_manager) => nonce_manager,
+            None => {
+                default_manager = NonceManager::new(self.rpc_pool.clone(), DEFAULT_NONCE_ACCOUNTS);
+                &default_manager
+            }
+        };
+
+        match args.command {
+            NonceCommands::List => {
+                for wallet in 1..WALLETS + 1 {
+                    let authority = self.signer_by_number(wallet).pubkey();
+                    println!("Wallet {} ({})", wallet, authority);
+                    for nonce_pubkey in nonce_manager.addresses(&authority) {
+                        let account = self
+                            .rpc_pool
+                            .read(|client| async move { client.get_account(&nonce_pubkey).await })
+                            .await;
+                        let Ok(account) = account else {
+                            println!("  {}: not created", nonce_pubkey);
+                            continue;
+                        };
+                        match nonce_utils::data_from_account(&account) {
+                            Ok(data) => println!(
+                                "  {}: blockhash {}, authority {}, balance {} SOL",
+                                nonce_pubkey,
+                                data.blockhash(),
+                                data.authority,
+                                lamports_to_sol(account.lamports)
+                            ),
+                            Err(err) => println!("  {}: {}", nonce_pubkey, err),
+                        }
+                    }
+                }
+            }
+            NonceCommands::Create => {
+                for wallet in 1..WALLETS + 1 {
+                    let signer = self.signer_by_number(wallet);
+                    for idx in 0..nonce_manager.capacity {
+                        match nonce_manager.create(&signer, idx).await {
+                            Ok(Some(nonce_pubkey)) => println!("Created nonce account {}", nonce_pubkey),
+                            Ok(None) => {}
+                            Err(err) => println!("Failed to create nonce account for wallet {}: {:?}", wallet, err),
+                        }
+                    }
+                }
+            }
+            NonceCommands::Advance(args) => {
+                for (signer, nonce_pubkey) in self.select_nonce_accounts(nonce_manager, args.address).await {
+                    match nonce_manager.advance(&signer, &nonce_pubkey).await {
+                        Ok(sig) => println!("Advanced nonce account {}: {}", nonce_pubkey, sig),
+                        Err(err) => println!("Failed to advance nonce account {}: {:?}", nonce_pubkey, err),
+                    }
+                }
+            }
+            NonceCommands::Withdraw(args) => {
+                for (signer, nonce_pubkey) in self.select_nonce_accounts(nonce_manager, Some(args.address)).await {
+                    // Partial withdrawals must leave the account rent exempt
+                    let lamports = sol_to_lamports(args.amount);
+                    let balance = self
+                        .rpc_pool
+                        .read(|client| async move { client.get_balance(&nonce_pubkey).await })
+                        .await
+                        .unwrap_or_default();
+                    let rent = self
+                        .rpc_pool
+                        .read(|client| async move { client.get_minimum_balance_for_rent_exemption(State::size()).await })
+                        .await
+                        .unwrap_or_default();
+                    if lamports != balance && lamports.saturating_add(rent) > balance {
+                        println!(
+                            "Can withdraw at most {} SOL from {} without closing it",
+                            lamports_to_sol(balance.saturating_sub(rent)),
+                            nonce_pubkey
+                        );
+                        continue;
+                    }
+                    match nonce_manager.withdraw(&signer, &nonce_pubkey, lamports).await {
+                        Ok(sig) => println!("Withdrew {} SOL from nonce account {}: {}", args.amount, nonce_pubkey, sig),
+                        Err(err) => println!("Failed to withdraw from nonce account {}: {:?}", nonce_pubkey, err),
+                    }
+                }
+            }
+            NonceCommands::Close(args) => {
+                for (signer, nonce_pubkey) in self.select_nonce_accounts(nonce_manager, args.address).await {
+                    let balance = self
+                        .rpc_pool
+                        .read(|client| async move { client.get_balance(&nonce_pubkey).await })
+                        .await;
+                    let balance = match balance {
+                        Ok(balance) => balance,
+                        Err(err) => {
+                            println!("Failed to fetch nonce account {}: {:?}", nonce_pubkey, err);
+                            continue;
+                        }
+                    };
+                    match nonce_manager.withdraw(&signer, &nonce_pubkey, balance).await {
+                        Ok(sig) => println!("Closed nonce account {}, reclaimed {} SOL: {}", nonce_pubkey, lamports_to_sol(balance), sig),
+                        Err(err) => println!("Failed to close nonce account {}: {:?}", nonce_pubkey, err),
+                    }
+                }
+            }
+        }
+    }
+
+    // Existing managed nonce accounts with their authority, either the one at `address` or all of them
+    async fn select_nonce_accounts(&self, nonce_manager: &NonceManager, address: Option<String>) -> Vec<(Keypair, Pubkey)> {
+        let address = address.map(|address| Pubkey::from_str(&address).expect("Failed to parse nonce account address"));
+        let mut accounts = Vec::new();
+        for wallet in 1..WALLETS + 1 {
+            let signer = self.signer_by_number(wallet);
+            for nonce_pubkey in nonce_manager.addresses(&signer.pubkey()) {
+                if address.is_some_and(|address| address != nonce_pubkey) {
+                    continue;
+                }
+                if nonce_manager.fetch(&nonce_pubkey).await.is_ok() {
+                    accounts.push((signer.insecure_clone(), nonce_pubkey));
+                }
+            }
+        }
+        if accounts.is_empty() {
+            println!("No matching nonce accounts found");
+        }
+        accounts
+    }
+}
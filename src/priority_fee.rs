@@ -0,0 +1,144 @@
+use std::{str::FromStr, sync::atomic::Ordering};
+
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+use solana_sdk::compute_budget::{self, ComputeBudgetInstruction};
+
+use crate::Miner;
+
+const DEFAULT_PERCENTILE: u8 = 50;
+
+const DEFAULT_ESCALATION_EVERY: usize = 10;
+
+// Compute budget instruction discriminator
+const SET_COMPUTE_UNIT_PRICE: u8 = 3;
+
+// How much to pay per compute unit
+#[derive(Clone, Copy, Debug)]
+pub enum PriorityFee {
+    // A fixed number of microlamports
+    Fixed(u64),
+    // A percentile of the fees recently paid to write the same accounts
+    Auto(u8),
+}
+
+// Parses a microlamport amount or `auto[:percentile]`, e.g. `auto:75`
+impl FromStr for PriorityFee {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || format!("invalid priority fee `{}`, expected microlamports or auto[:percentile]", s);
+        let (name, percentile) = match s.split_once(':') {
+            Some((name, percentile)) => (name, Some(percentile)),
+            None => (s, None),
+        };
+        if !name.eq_ignore_ascii_case("auto") {
+            if percentile.is_some() {
+                return Err(invalid());
+            }
+            return s.parse().map(PriorityFee::Fixed).map_err(|_| invalid());
+        }
+        let percentile = match percentile {
+            Some(percentile) => percentile
+                .parse::<u8>()
+                .ok()
+                .filter(|percentile| *percentile <= 100)
+                .ok_or_else(|| format!("invalid percentile `{}`, expected 0-100", percentile))?,
+            None => DEFAULT_PERCENTILE,
+        };
+        Ok(PriorityFee::Auto(percentile))
+    }
+}
+
+impl Miner {
+    // Microlamports per compute unit for a transaction writing to `accounts`, capped by
+    // the max priority fee. Falls back to the last known fee if the lookup fails.
+    pub async fn get_priority_fee(&self, accounts: &[Pubkey]) -> u64 {
+        let fee = match self.priority_fee {
+            PriorityFee::Fixed(fee) => fee,
+            PriorityFee::Auto(percentile) => {
+                match self.chain.recent_priority_fees(accounts.to_vec()).await {
+                    Ok(fees) => fee_at_percentile(fees, percentile),
+                    Err(err) => {
+                        println!("Error fetching recent priority fees: {:?}", err);
+                        self.last_priority_fee.load(Ordering::Relaxed)
+                    }
+                }
+            }
+        };
+        let fee = match self.max_priority_fee {
+            Some(max_priority_fee) => fee.min(max_priority_fee),
+            None => fee,
+        };
+        self.last_priority_fee.store(fee, Ordering::Relaxed);
+        fee
+    }
+}
+
+fn fee_at_percentile(mut fees: Vec<u64>, percentile: u8) -> u64 {
+    if fees.is_empty() {
+        return 0;
+    }
+    fees.sort_unstable();
+    fees[(fees.len() - 1) * percentile as usize / 100]
+}
+
+// Raises the priority fee by `multiplier` after every `every` failed sends
+#[derive(Clone, Copy, Debug)]
+pub struct FeeEscalation {
+    pub multiplier: f64,
+    pub every: usize,
+}
+
+// Parses `multiplier[:every]`, e.g. `1.5:10`
+impl FromStr for FeeEscalation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid fee escalation `{}`, expected MULTIPLIER[:EVERY_ATTEMPTS]", s);
+        let (multiplier, every) = match s.trim().split_once(':') {
+            Some((multiplier, every)) => (multiplier, every.parse().map_err(|_| invalid())?),
+            None => (s.trim(), DEFAULT_ESCALATION_EVERY),
+        };
+        let multiplier: f64 = multiplier.parse().map_err(|_| invalid())?;
+        if multiplier < 1.0 || every == 0 {
+            return Err(invalid());
+        }
+        Ok(Self { multiplier, every })
+    }
+}
+
+impl FeeEscalation {
+    // The fee to pay after `attempts` failed sends, never above `cap`
+    pub fn fee(&self, start_fee: u64, attempts: usize, cap: u64) -> u64 {
+        let steps = attempts / self.every;
+        if steps == 0 {
+            return start_fee.min(cap);
+        }
+        let fee = start_fee.max(1) as f64 * self.multiplier.powi(steps.min(i32::MAX as usize) as i32);
+        (fee as u64).min(cap)
+    }
+}
+
+// The compute unit price set by a compute budget instruction in `ixs`, if any
+pub fn compute_unit_price(ixs: &[Instruction]) -> Option<u64> {
+    ixs.iter().find_map(|ix| {
+        if ix.program_id != compute_budget::id() || ix.data.first() != Some(&SET_COMPUTE_UNIT_PRICE) {
+            return None;
+        }
+        ix.data.get(1..9).map(|data| u64::from_le_bytes(data.try_into().unwrap()))
+    })
+}
+
+// Replaces the compute unit price in `ixs`
+pub fn with_compute_unit_price(ixs: &[Instruction], fee: u64) -> Vec<Instruction> {
+    ixs.iter()
+        .map(|ix| {
+            if ix.program_id == compute_budget::id() && ix.data.first() == Some(&SET_COMPUTE_UNIT_PRICE) {
+                ComputeBudgetInstruction::set_compute_unit_price(fee)
+            } else {
+                ix.clone()
+            }
+        })
+        .collect()
+}
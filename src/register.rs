@@ -2,7 +2,7 @@ use solana_sdk::{
     signature::Signer,
     signer::keypair::Keypair
 };
-use crate::{utils::proof_pubkey, Miner};
+use crate::Miner;
 
 impl Miner {
     pub async fn register_by_number(&self, keypair_number: u64) {
@@ -11,10 +11,8 @@ impl Miner {
 
     pub async fn register(&self, signer: &Keypair) {
         // Return early if miner is already registered
-        let client = self.rpc_client.clone();
         let pubkey = signer.pubkey();
-        let proof_address = proof_pubkey(pubkey);
-        if client.get_account(&proof_address).await.is_ok() {
+        if self.fetch_proof(pubkey).await.is_ok() {
             println!("Registration OK...");    
             return;
         }
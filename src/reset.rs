@@ -0,0 +1,48 @@
+use std::str::FromStr;
+
+use rand::Rng;
+
+// When this miner should pay for the epoch reset transaction
+#[derive(Clone, Copy, Debug)]
+pub enum ResetPolicy {
+    Never,
+    Always,
+    // Reset with 1-in-`odds` chance each time the epoch is found overdue
+    Random(u64),
+    // Reset once the epoch has been overdue for this many seconds
+    Stuck(i64),
+}
+
+impl ResetPolicy {
+    pub fn should_reset(&self, overdue: i64) -> bool {
+        match *self {
+            ResetPolicy::Never => false,
+            ResetPolicy::Always => true,
+            ResetPolicy::Random(odds) => rand::thread_rng().gen_range(0..odds.max(1)).eq(&0),
+            ResetPolicy::Stuck(seconds) => overdue >= seconds,
+        }
+    }
+}
+
+// Parses `never`, `always`, `random:<odds>` and `stuck:<seconds>`
+impl FromStr for ResetPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (s, None),
+        };
+        let invalid = || format!("invalid reset policy parameter in `{}`", s);
+        match (name, param) {
+            ("never", None) => Ok(ResetPolicy::Never),
+            ("always", None) => Ok(ResetPolicy::Always),
+            ("random", Some(odds)) => odds.parse().map(ResetPolicy::Random).map_err(|_| invalid()),
+            ("stuck", Some(seconds)) => seconds.parse().map(ResetPolicy::Stuck).map_err(|_| invalid()),
+            _ => Err(format!(
+                "unknown reset policy `{}`, expected never, always, random:<odds> or stuck:<seconds>",
+                s
+            )),
+        }
+    }
+}
@@ -3,7 +3,7 @@ use std::str::FromStr;
 use solana_program::pubkey::Pubkey;
 use solana_sdk::signature::Signer;
 
-use crate::{utils::get_proof, Miner};
+use crate::Miner;
 
 impl Miner {
     pub async fn rewards_by_number(&self, keypair_number: u64) {
@@ -21,7 +21,13 @@ impl Miner {
         } else {
             self.signer().pubkey()
         };
-        let proof = get_proof(&self.rpc_client, address).await;
+        let proof = match self.fetch_proof(address).await {
+            Ok(proof) => proof,
+            Err(err) => {
+                println!("Failed to get miner account: {:?}", err);
+                return;
+            }
+        };
         let amount = (proof.claimable_rewards as f64) / 10f64.powf(ore::TOKEN_DECIMALS as f64);
         println!("{:} ORE", amount);
     }
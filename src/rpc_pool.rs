@@ -0,0 +1,220 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::{future::join_all, stream::FuturesUnordered, StreamExt};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::SerializableTransaction,
+    rpc_config::RpcSendTransactionConfig,
+    rpc_request::RpcError,
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    signature::Signature,
+    transaction::TransactionError,
+};
+
+// How often to re-check endpoint health while reading
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+// Endpoints further than this many slots behind the most recent one are unhealthy
+const MAX_SLOT_LAG: u64 = 25;
+
+// Rounds of retries across all endpoints, with the delay doubling each round
+const READ_RETRIES: u32 = 4;
+const READ_BACKOFF: Duration = Duration::from_millis(500);
+
+pub struct RpcEndpoint {
+    // Host of the endpoint, without any API key in the path or query
+    pub label: String,
+    pub client: Arc<RpcClient>,
+}
+
+// Every configured RPC endpoint. Reads go to the primary and fail over to the others,
+// sends go to all of them.
+pub struct RpcPool {
+    pub endpoints: Vec<RpcEndpoint>,
+    primary: AtomicUsize,
+    last_health_check: Mutex<Option<Instant>>,
+}
+
+impl RpcPool {
+    pub fn new(urls: Vec<String>, commitment: CommitmentConfig) -> Self {
+        let endpoints = urls
+            .into_iter()
+            .map(|url| RpcEndpoint {
+                label: endpoint_label(&url),
+                client: Arc::new(RpcClient::new_with_commitment(url, commitment)),
+            })
+            .collect();
+        Self {
+            endpoints,
+            primary: AtomicUsize::new(0),
+            last_health_check: Mutex::new(None),
+        }
+    }
+
+    // Checks every endpoint's health and slot. Reads stay on the primary while it is
+    // healthy and caught up, and otherwise move to the healthy endpoint furthest ahead.
+    pub async fn check_health(&self) {
+        *self.last_health_check.lock().unwrap() = Some(Instant::now());
+        let checks = join_all(self.endpoints.iter().map(|endpoint| async move {
+            let healthy = endpoint.client.get_health().await.is_ok();
+            let slot = endpoint.client.get_slot().await.ok();
+            (healthy, slot)
+        }))
+        .await;
+        let max_slot = checks.iter().filter_map(|(_, slot)| *slot).max().unwrap_or_default();
+        let is_healthy = |(healthy, slot): &(bool, Option<u64>)| {
+            *healthy && slot.is_some_and(|slot| max_slot.saturating_sub(slot) <= MAX_SLOT_LAG)
+        };
+        let primary = self.primary.load(Ordering::Relaxed);
+        if is_healthy(&checks[primary]) {
+            return;
+        }
+        let healthiest = checks
+            .iter()
+            .enumerate()
+            .filter(|(_, check)| is_healthy(check))
+            .max_by_key(|(_, (_, slot))| *slot)
+            .map(|(idx, _)| idx);
+        match healthiest {
+            Some(idx) => {
+                println!("{} is unhealthy, routing reads to {}", self.endpoints[primary].label, self.endpoints[idx].label);
+                self.primary.store(idx, Ordering::Relaxed);
+            }
+            None => println!("Warning: no healthy RPC endpoints"),
+        }
+    }
+
+    async fn check_health_if_due(&self) {
+        if self.endpoints.len() < 2 {
+            return;
+        }
+        let due = match *self.last_health_check.lock().unwrap() {
+            Some(last_health_check) => last_health_check.elapsed() >= HEALTH_CHECK_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.check_health().await;
+        }
+    }
+
+    // Runs a read against the primary, trying the other endpoints in turn if it fails.
+    // The first endpoint to answer becomes the new primary. Transient errors are retried
+    // with backoff, anything else is returned right away.
+    pub async fn read<T, F, Fut>(&self, f: F) -> ClientResult<T>
+    where
+        F: Fn(Arc<RpcClient>) -> Fut,
+        Fut: Future<Output = ClientResult<T>>,
+    {
+        self.check_health_if_due().await;
+        let mut last_err = None;
+        for attempt in 0..=READ_RETRIES {
+            if attempt > 0 {
+                tokio::time::sleep(READ_BACKOFF * 2u32.pow(attempt - 1)).await;
+            }
+            let primary = self.primary.load(Ordering::Relaxed);
+            for i in 0..self.endpoints.len() {
+                let idx = (primary + i) % self.endpoints.len();
+                match f(self.endpoints[idx].client.clone()).await {
+                    Ok(value) => {
+                        if idx != primary {
+                            println!("Failing over reads to {}", self.endpoints[idx].label);
+                            self.primary.store(idx, Ordering::Relaxed);
+                        }
+                        return Ok(value);
+                    }
+                    Err(err) if !is_transient(&err) => return Err(err),
+                    Err(err) => last_err = Some(err),
+                }
+            }
+        }
+        Err(last_err.unwrap())
+    }
+
+    // Sends to every endpoint at once. Succeeds if any of them accepted the transaction.
+    pub async fn send_all(
+        &self,
+        tx: &(impl SerializableTransaction + Sync),
+        config: RpcSendTransactionConfig,
+    ) -> ClientResult<Signature> {
+        let results = join_all(
+            self.endpoints
+                .iter()
+                .map(|endpoint| endpoint.client.send_transaction_with_config(tx, config)),
+        )
+        .await;
+        let mut last_err = None;
+        for result in results {
+            match result {
+                Ok(sig) => return Ok(sig),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap())
+    }
+
+    // Polls every endpoint for the signatures. Returns the first endpoint to report one of
+    // them at `commitment`, with the signature and the error it failed with.
+    pub async fn find_landed(
+        &self,
+        sigs: &[Signature],
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Option<(&str, Signature, Option<TransactionError>)>> {
+        let mut polls: FuturesUnordered<_> = self
+            .endpoints
+            .iter()
+            .map(|endpoint| async move { (endpoint, endpoint.client.get_signature_statuses(sigs).await) })
+            .collect();
+        let mut last_err = None;
+        let mut any_ok = false;
+        while let Some((endpoint, result)) = polls.next().await {
+            let statuses = match result {
+                Ok(statuses) => statuses.value,
+                Err(err) => {
+                    last_err = Some(err);
+                    continue;
+                }
+            };
+            any_ok = true;
+            let landed = sigs.iter().zip(statuses).find_map(|(sig, status)| {
+                let status = status?;
+                status
+                    .satisfies_commitment(commitment)
+                    .then(|| (*sig, status.err))
+            });
+            if let Some((sig, err)) = landed {
+                return Ok(Some((endpoint.label.as_str(), sig, err)));
+            }
+        }
+        match last_err {
+            Some(err) if !any_ok => Err(err),
+            _ => Ok(None),
+        }
+    }
+}
+
+// Network trouble and node errors may clear up, a missing account or bad data won't
+fn is_transient(err: &ClientError) -> bool {
+    matches!(
+        err.kind(),
+        ClientErrorKind::Io(_)
+            | ClientErrorKind::Reqwest(_)
+            | ClientErrorKind::RpcError(RpcError::RpcRequestError(_))
+            | ClientErrorKind::RpcError(RpcError::RpcResponseError { .. })
+    )
+}
+
+fn endpoint_label(url: &str) -> String {
+    let host = url.split("://").nth(1).unwrap_or(url);
+    let host = host.split(['/', '?']).next().unwrap_or(host);
+    host.rsplit('@').next().unwrap_or(host).to_string()
+}
@@ -0,0 +1,218 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveTime, Timelike};
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+// A daily window such as `22:00-06:00`, optionally limited to some days of the week with a
+// prefix like `mon-fri` or `sat,sun`. Windows that wrap past midnight belong to the day
+// they start on.
+#[derive(Clone, Debug)]
+struct Window {
+    days: [bool; 7],
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl Window {
+    fn contains(&self, now: DateTime<Local>) -> bool {
+        let today = now.weekday().num_days_from_monday() as usize;
+        let yesterday = (today + 6) % 7;
+        let time = now.time();
+        if self.start <= self.end {
+            self.days[today] && time >= self.start && time < self.end
+        } else {
+            (self.days[today] && time >= self.start) || (self.days[yesterday] && time < self.end)
+        }
+    }
+}
+
+// When mining is allowed, in local time
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    windows: Vec<Window>,
+}
+
+impl Schedule {
+    // Reads windows from a file, one per line. Blank lines and `#` comments are ignored.
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read schedule file {}: {}", path, err))?;
+        let windows = contents
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(parse_window)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { windows })
+    }
+
+    pub fn is_open(&self, now: DateTime<Local>) -> bool {
+        self.windows.iter().any(|window| window.contains(now))
+    }
+
+    // The next minute at which mining is allowed, searching up to a week ahead
+    pub fn next_open(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = now.with_second(0)?.with_nanosecond(0)?;
+        (1..=7 * 24 * 60)
+            .map(|minutes| start + Duration::minutes(minutes))
+            .find(|time| self.is_open(*time))
+    }
+}
+
+// Parses a comma-separated list of windows, e.g. `22:00-06:00,sat,sun 00:00-24:00`
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut windows = Vec::new();
+        let mut days: Option<String> = None;
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            // Day lists like `sat,sun 00:00-24:00` are split by the comma above
+            if !part.contains(':') {
+                days = Some(match days {
+                    Some(days) => format!("{},{}", days, part),
+                    None => part.to_string(),
+                });
+                continue;
+            }
+            let window = match days.take() {
+                Some(days) => format!("{},{}", days, part),
+                None => part.to_string(),
+            };
+            windows.push(parse_window(&window)?);
+        }
+        if let Some(days) = days {
+            return Err(format!("days `{}` in schedule `{}` have no time range", days, s));
+        }
+        if windows.is_empty() {
+            return Err(format!("no mining windows in schedule `{}`", s));
+        }
+        Ok(Self { windows })
+    }
+}
+
+fn parse_window(s: &str) -> Result<Window, String> {
+    let invalid = || format!("invalid schedule window `{}`, expected e.g. `mon-fri 22:00-06:00`", s);
+    let (days, times) = match s.rsplit_once(' ') {
+        Some((days, times)) => (Some(days.trim()), times.trim()),
+        None => (None, s.trim()),
+    };
+    let days = match days {
+        Some(days) => parse_days(days).ok_or_else(invalid)?,
+        None => [true; 7],
+    };
+    let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+    let start = parse_time(start).ok_or_else(invalid)?;
+    let end = parse_time(end).ok_or_else(invalid)?;
+    Ok(Window { days, start, end })
+}
+
+// Accepts `24:00` as the end of the day
+fn parse_time(s: &str) -> Option<NaiveTime> {
+    if s.trim() == "24:00" {
+        return NaiveTime::from_hms_nano_opt(23, 59, 59, 999_999_999);
+    }
+    NaiveTime::parse_from_str(s.trim(), "%H:%M").ok()
+}
+
+fn parse_days(s: &str) -> Option<[bool; 7]> {
+    let day = |name: &str| DAYS.iter().position(|day| day.eq_ignore_ascii_case(name.trim()));
+    let mut days = [false; 7];
+    for part in s.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (day(first)?, day(last)?);
+                let mut i = first;
+                loop {
+                    days[i] = true;
+                    if i == last {
+                        break;
+                    }
+                    i = (i + 1) % 7;
+                }
+            }
+            None => days[day(part)?] = true,
+        }
+    }
+    Some(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    // 2024-01-01 was a Monday
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 1, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_windows_with_and_without_days() {
+        let schedule: Schedule = "22:00-06:00,sat,sun 09:00-17:00".parse().unwrap();
+        assert_eq!(schedule.windows.len(), 2);
+        assert_eq!(schedule.windows[0].days, [true; 7]);
+        assert_eq!(schedule.windows[1].days, [false, false, false, false, false, true, true]);
+        assert_eq!(schedule.windows[1].start, NaiveTime::from_hms_opt(9, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn parses_day_ranges_that_wrap_the_week() {
+        let window = parse_window("fri-mon 10:00-11:00").unwrap();
+        assert_eq!(window.days, [true, false, false, false, true, true, true]);
+    }
+
+    #[test]
+    fn rejects_malformed_schedules() {
+        assert!("".parse::<Schedule>().is_err());
+        assert!("22:00".parse::<Schedule>().is_err());
+        assert!("25:00-06:00".parse::<Schedule>().is_err());
+        assert!("someday 22:00-06:00".parse::<Schedule>().is_err());
+        assert!("22:00-06:00,sat".parse::<Schedule>().is_err());
+        assert!("22:00-06:00,sat,sun".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn windows_past_midnight_belong_to_the_day_they_start() {
+        let schedule: Schedule = "fri 22:00-06:00".parse().unwrap();
+        assert!(!schedule.is_open(at(5, 21, 59)));
+        assert!(schedule.is_open(at(5, 22, 0)));
+        // Saturday morning is still Friday's window
+        assert!(schedule.is_open(at(6, 5, 59)));
+        assert!(!schedule.is_open(at(6, 6, 0)));
+        // Friday morning would be Thursday's
+        assert!(!schedule.is_open(at(5, 1, 0)));
+    }
+
+    #[test]
+    fn end_of_day_covers_the_last_minute() {
+        let schedule: Schedule = "sat 00:00-24:00".parse().unwrap();
+        assert!(schedule.is_open(at(6, 0, 0)));
+        assert!(schedule.is_open(at(6, 23, 59)));
+        assert!(!schedule.is_open(at(7, 0, 0)));
+        assert!(!schedule.is_open(at(5, 23, 59)));
+    }
+
+    #[test]
+    fn finds_the_next_opening() {
+        let schedule: Schedule = "mon-fri 09:00-17:00".parse().unwrap();
+        assert_eq!(schedule.next_open(at(1, 17, 30)), Some(at(2, 9, 0)));
+        // Over the weekend
+        assert_eq!(schedule.next_open(at(6, 12, 0)), Some(at(8, 9, 0)));
+        assert_eq!(schedule.next_open(at(1, 10, 0)), Some(at(1, 10, 1)));
+    }
+
+    #[test]
+    fn never_opens_without_matching_days() {
+        let schedule = Schedule {
+            windows: vec![Window {
+                days: [false; 7],
+                start: NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            }],
+        };
+        assert_eq!(schedule.next_open(at(1, 0, 0)), None);
+    }
+}
@@ -1,111 +1,89 @@
 use std::{
     io::{stdout, Write},
+    sync::atomic::Ordering,
     time::Duration,
 };
 use solana_client::{
-    nonblocking::rpc_client::RpcClient,
     client_error::{ClientError, ClientErrorKind, Result as ClientResult},
     rpc_config::{RpcSendTransactionConfig},
 };
 use solana_program::instruction::Instruction;
 use solana_sdk::{
-    commitment_config::{CommitmentConfig, CommitmentLevel},
+    commitment_config::CommitmentLevel,
+    pubkey::Pubkey,
     signature::{Signature,Signer, Keypair},
-    transaction::Transaction,
+    transaction::{Transaction, TransactionError, VersionedTransaction},
 };
 
-use solana_transaction_status::{TransactionConfirmationStatus, UiTransactionEncoding};
+use solana_transaction_status::UiTransactionEncoding;
 
-const NONCE_RENT: u64 = 1_447_680;
-
-pub struct NonceManager {
-    pub rpc_client: std::sync::Arc<RpcClient>,
-    pub authority: solana_sdk::pubkey::Pubkey,
-    pub capacity: u64,
-    pub idx: u64,
-}
-impl NonceManager {
-    pub fn new(rpc_client: std::sync::Arc<RpcClient>, authority: solana_sdk::pubkey::Pubkey, capacity: u64) -> Self {
-        NonceManager {
-            rpc_client,
-            authority,
-            capacity,
-            idx: 0,
-        }
-    }
-
-    pub async fn try_init_all(&mut self, payer: &solana_sdk::signer::keypair::Keypair) -> Vec<Result<Signature, solana_client::client_error::ClientError>> {
-        let (blockhash, _) = self.rpc_client
-            .get_latest_blockhash_with_commitment(CommitmentConfig::finalized()).await
-            .unwrap_or_default();
-        let mut sigs = vec![];
-        for _ in 0..self.capacity {
-            let nonce_account = self.next();
-            let ixs = self.maybe_create_ixs(&nonce_account.pubkey()).await;
-            if ixs.is_none() {
-                continue;
-            }
-            let ixs = ixs.unwrap();
-            let tx = Transaction::new_signed_with_payer(&ixs, Some(&payer.pubkey()), &[&payer, &nonce_account], blockhash);
-            sigs.push(self.rpc_client.send_transaction(&tx).await);
-        }
-        sigs
-    }
-
-    fn next_seed(&mut self) -> u64 {
-        let ret = self.idx;
-        self.idx = (self.idx + 1) % self.capacity;
-        ret
-    }
-
-    pub fn next(&mut self) -> solana_sdk::signer::keypair::Keypair {
-        let seed = format!("Nonce:{}:{}", self.authority.clone(), self.next_seed());
-        let seed = sha256::digest(seed.as_bytes());
-        let kp = solana_sdk::signer::keypair::keypair_from_seed(&seed.as_ref()).unwrap();
-        kp
-    }
-
-    pub async fn maybe_create_ixs(&mut self, nonce: &solana_sdk::pubkey::Pubkey) -> Option<Vec<Instruction>> {
-        if solana_client::nonce_utils::nonblocking::get_account(&self.rpc_client, nonce).await.is_ok() {
-            None
-        } else {
-            Some(solana_sdk::system_instruction::create_nonce_account(
-                    &self.authority,
-                    &nonce,
-                    &self.authority,
-                    NONCE_RENT,
-            ))
-        }
-    }
-}
-
-use crate::Miner;
+use crate::{
+    budget::{estimate_fee, LAMPORTS_PER_SIGNATURE},
+    priority_fee::{compute_unit_price, with_compute_unit_price},
+    Miner,
+};
 
 const RPC_RETRIES: usize = 0;
-const GATEWAY_RETRIES: usize = 75;
-const CONFIRM_RETRIES: usize = 1;
 
-const CONFIRM_DELAY: u64 = 0;
-const GATEWAY_DELAY: u64 = 600;
+// Milliseconds between rebroadcasts, signature status polls and blockhash expiry checks
+const REBROADCAST_INTERVAL: u64 = 600;
+const STATUS_INTERVAL: u64 = 400;
+const EXPIRY_CHECK_INTERVAL: u64 = 2000;
 
 impl Miner {
     pub async fn send_and_confirm(
         &self,
         ixs: &[Instruction],
-        _dynamic_cus: bool,
+        dynamic_cus: bool,
         skip_confirm: bool,
         signers: Vec<&Keypair>,
     ) -> ClientResult<Signature> {
         let mut stdout = stdout();
         let signer = signers[0];
-        let client = self.rpc_client.clone();
-        
-        let mut nonce_manager = NonceManager::new(self.rpc_client.clone(), signer.pubkey(), 1 as u64);
-        nonce_manager.try_init_all(&signer).await; 
+
+        // Size the compute budget to what the transaction actually uses
+        let calibrated_ixs = if dynamic_cus {
+            self.calibrate_cu_limit(ixs, &signers).await
+        } else {
+            ixs.to_vec()
+        };
+        let ixs = calibrated_ixs.as_slice();
+
+        // Stay within the fee limits
+        let mut tx_fee = estimate_fee(ixs, signers.len());
+        if let Err(reason) = self.budget.check(tx_fee) {
+            println!("{}", reason);
+            return Err(ClientError {
+                request: None,
+                kind: ClientErrorKind::Custom(reason),
+            });
+        }
+
+        // Chains that run in-process land the transaction right away
+        let signer_pubkeys: Vec<Pubkey> = signers.iter().map(|signer| signer.pubkey()).collect();
+        if let Some(result) = self.chain.process(ixs, &signer_pubkeys) {
+            self.metrics.tx_attempts.fetch_add(1, Ordering::Relaxed);
+            return match result {
+                Ok(sig) => {
+                    self.metrics.tx_landed.fetch_add(1, Ordering::Relaxed);
+                    self.budget.record(tx_fee);
+                    Ok(sig)
+                }
+                Err((idx, err)) => {
+                    self.metrics.tx_failed.fetch_add(1, Ordering::Relaxed);
+                    Err(ClientError {
+                        request: None,
+                        kind: ClientErrorKind::Custom(format!("Instruction {} failed: {}", idx, err)),
+                    })
+                }
+            };
+        }
+
 
         // Return error if balance is zero
-        let balance = client.get_balance(&signer.pubkey()).await.unwrap();
-        if balance <= 0 {
+        let balance = self.fetch_balance(&signer.pubkey()).await?;
+        if balance == 0 {
+            self.metrics.tx_failed.fetch_add(1, Ordering::Relaxed);
             return Err(ClientError {
                 request: None,
                 kind: ClientErrorKind::Custom("Insufficient SOL balance".into()),
@@ -113,12 +91,6 @@ impl Miner {
         }
 
         // Build tx
-        /*
-        let (_hash, slot) = client
-            .get_latest_blockhash_with_commitment(self.rpc_client.commitment())
-            .await
-            .unwrap();
-        */
         let send_cfg = RpcSendTransactionConfig {
             skip_preflight: true,
             preflight_commitment: Some(CommitmentLevel::Confirmed),
@@ -127,94 +99,227 @@ impl Miner {
             min_context_slot: None,
         };
         
-        let msg = solana_sdk::message::Message::new_with_nonce( 
-            ixs.to_vec(),
-            Some(&signer.pubkey()),
-            &nonce_manager.next().pubkey(),
-            &signer.pubkey()
-        );
-        let mut tx = Transaction::new_unsigned(msg.clone());
-        
-        //let mut tx = Transaction::new_with_payer(ixs, Some(&signer.pubkey()));
-
         // Update hash before sending transactions
-        let (hash, _slot) = client
-            .get_latest_blockhash_with_commitment(self.rpc_client.commitment())
-            .await
-            .unwrap();
+        let (hash, last_valid_block_height) = self
+            .rpc_pool
+            .read(|client| async move { client.get_latest_blockhash_with_commitment(client.commitment()).await })
+            .await?;
+
+        // Sign against a durable nonce when enabled, otherwise the recent blockhash
+        let nonce = match &self.nonce_manager {
+            Some(nonce_manager) => {
+                nonce_manager.ensure_accounts(signer).await?;
+                Some(nonce_manager.next(&signer.pubkey()).await?)
+            }
+            None => None,
+        };
 
         // Submit tx
         // add all the signers
-        tx.sign(&signers, hash);
+        let sign = |ixs: Vec<Instruction>| {
+            let msg = match nonce {
+                Some((nonce_pubkey, nonce_hash)) => {
+                    self.compile_message(&ixs, &signer.pubkey(), Some(&nonce_pubkey), nonce_hash)
+                }
+                None => self.compile_message(&ixs, &signer.pubkey(), None, hash),
+            };
+            VersionedTransaction::try_new(msg, &signers).expect("Failed to sign transaction")
+        };
+        let mut tx = sign(ixs.to_vec());
+        let tip_tx = self
+            .bundle_sender
+            .as_ref()
+            .map(|bundle_sender| bundle_sender.tip_transaction(signer, hash));
+
+        //let mut tx = Transaction::new_with_payer(ixs, Some(&signer.pubkey()));
+
+        // Bundles also pay for their tip transaction, which is only charged if it lands
+        let tip_cost = self.bundle_sender.as_ref().map_or(0, |bundle_sender| bundle_sender.tip_cost());
+        let tip_sig = tip_tx.as_ref().map(|tip_tx| tip_tx.signatures[0]);
+        let mut bundled = false;
+
+        // Every signed version of the transaction stays in flight, so any of them can land
+        let start_fee = compute_unit_price(ixs);
+        let mut fee = start_fee;
+        let mut sigs = vec![tx.signatures[0]];
 
-        //let mut sigs = vec![];
+        // Rebroadcast until one of the signatures lands or the blockhash expires
         let miningchars = ["\u{1FAA8}","\u{26CF} ","\u{1F48E}"];
         let mut attempts = 0;
+        let mut rebroadcast = tokio::time::interval(Duration::from_millis(REBROADCAST_INTERVAL));
+        let mut status_poll = tokio::time::interval(Duration::from_millis(STATUS_INTERVAL));
+        let mut expiry_check = tokio::time::interval(Duration::from_millis(EXPIRY_CHECK_INTERVAL));
         loop {
+            tokio::select! {
+                _ = rebroadcast.tick() => {
+                    // Raise the priority fee if the transaction keeps failing to land
+                    if let (Some(escalation), Some(start_fee), Some(cap)) = (self.fee_escalation, start_fee, self.max_priority_fee) {
+                        let next_fee = escalation.fee(start_fee, attempts, cap);
+                        let next_ixs = with_compute_unit_price(ixs, next_fee);
+                        let next_tx_fee = estimate_fee(&next_ixs, signers.len());
+                        let tip_reserve = if bundled { tip_cost } else { 0 };
+                        if Some(next_fee) != fee && self.budget.check(next_tx_fee + tip_reserve).is_ok() {
+                            println!("\nRaising priority fee to {} micro-lamports", next_fee);
+                            fee = Some(next_fee);
+                            tx_fee = next_tx_fee;
+                            tx = sign(next_ixs);
+                            sigs.push(tx.signatures[0]);
+                        }
+                    }
 
-            match client.send_transaction_with_config(&tx, send_cfg).await {
-                Ok(sig) => {
-                    //sigs.push(sig);
-                    print!("{}", miningchars[attempts%3]);
+                    // Only send a bundle while the budget still covers its tip
+                    let tip_tx = tip_tx
+                        .as_ref()
+                        .filter(|_| bundled || self.budget.check(tx_fee + tip_cost).is_ok());
+                    self.metrics.tx_attempts.fetch_add(1, Ordering::Relaxed);
+                    match self.broadcast(&tx, tip_tx, send_cfg).await {
+                        Ok((sig, bundled_now)) => {
+                            bundled |= bundled_now;
+                            print!("{}", miningchars[attempts%3]);
+                            // Without confirming, there's no telling whether a tip was paid
+                            if skip_confirm {
+                                self.budget.record(tx_fee);
+                                return Ok(sig);
+                            }
+                        }
 
-                    // Confirm tx
-                    if skip_confirm {
-                        return Ok(sig);
+                        // Handle submit errors
+                        Err(err) => {
+                            println!("\nError {:?}", err);
+                        }
+                    }
+                    attempts += 1;
+                }
+                _ = status_poll.tick() => {
+                    if let Some((sig, err)) = self.find_landed(&sigs).await {
+                        return self.finish_landed(sig, err, tx_fee, tip_sig.filter(|_| bundled)).await;
                     }
-                    std::thread::sleep(Duration::from_millis(300));
-                    for _ in 0..CONFIRM_RETRIES {
-                        match client.get_signature_statuses(&[sig]).await {
-                            Ok(signature_statuses) => {
-                                //println!("Confirms: {:?}", signature_statuses.value);
-                                for signature_status in signature_statuses.value {
-                                    if let Some(signature_status) = signature_status.as_ref() {
-                                        if signature_status.confirmation_status.is_some() {
-                                            let current_commitment = signature_status
-                                                .confirmation_status
-                                                .as_ref()
-                                                .unwrap();
-                                            match current_commitment {
-                                                TransactionConfirmationStatus::Processed => {}
-                                                TransactionConfirmationStatus::Confirmed
-                                                | TransactionConfirmationStatus::Finalized => {
-                                                    println!("{} Success: Transaction landed! sig: {}", chrono::offset::Local::now(), sig);
-                                                    return Ok(sig);
-                                                }
-                                            }
-                                        } else {
-                                            println!("No status");
-                                        }
-                                    }
+                }
+                _ = expiry_check.tick() => {
+                    let block_height = self.rpc_pool.read(|client| async move { client.get_block_height().await }).await;
+                    let past_deadline = matches!(block_height, Ok(block_height) if block_height > last_valid_block_height);
+                    // A durable nonce transaction doesn't expire with the blockhash, only once
+                    // its nonce has moved on. Past the blockhash's lifetime it is given up on,
+                    // advancing the nonce so it can't land later.
+                    let expired = match (&self.nonce_manager, nonce) {
+                        (Some(nonce_manager), Some((nonce_pubkey, nonce_hash))) => {
+                            let nonce_advanced = matches!(
+                                nonce_manager.fetch(&nonce_pubkey).await,
+                                Ok(data) if data.blockhash() != nonce_hash
+                            );
+                            if !nonce_advanced && past_deadline {
+                                println!("\nGiving up, advancing nonce account {}", nonce_pubkey);
+                                match nonce_manager.advance(signer, &nonce_pubkey).await {
+                                    Ok(_sig) => self.budget.record(LAMPORTS_PER_SIGNATURE),
+                                    Err(err) => println!("Failed to advance nonce account {}: {:?}", nonce_pubkey, err),
                                 }
                             }
-                            // Handle confirmation errors
-                            Err(err) => {
-                                println!("\nError: {:?}", err);
-                            }
+                            nonce_advanced || past_deadline
                         }
-                        stdout.flush().ok();
-                        std::thread::sleep(Duration::from_millis(CONFIRM_DELAY));
+                        _ => past_deadline,
+                    };
+                    if !expired {
+                        continue;
                     }
-                    //println!("Transaction did not land");
+                    // It may have landed right before expiring
+                    if let Some((sig, err)) = self.find_landed(&sigs).await {
+                        return self.finish_landed(sig, err, tx_fee, tip_sig.filter(|_| bundled)).await;
+                    }
+                    if nonce.is_some() {
+                        println!("\n{} Expired: nonce account has advanced", chrono::offset::Local::now());
+                    } else {
+                        println!("\n{} Expired: blockhash is no longer valid", chrono::offset::Local::now());
+                    }
+                    self.metrics.tx_failed.fetch_add(1, Ordering::Relaxed);
+                    return Err(ClientError {
+                        request: None,
+                        kind: ClientErrorKind::Custom("Transaction expired".into()),
+                    });
                 }
+            }
+            stdout.flush().ok();
+        }
+    }
+
+    // Sends as a bundle when given a tip transaction, then over QUIC when the TPU client
+    // is enabled, falling back to every RPC endpoint if neither took the transaction.
+    // Also returns whether a bundle was accepted, which means the tip is owed.
+    async fn broadcast(
+        &self,
+        tx: &VersionedTransaction,
+        tip_tx: Option<&Transaction>,
+        config: RpcSendTransactionConfig,
+    ) -> ClientResult<(Signature, bool)> {
+        let wire_tx = bincode::serialize(tx).unwrap();
+        if let (Some(bundle_sender), Some(tip_tx)) = (&self.bundle_sender, tip_tx) {
+            match bundle_sender.send(&[wire_tx.clone(), bincode::serialize(tip_tx).unwrap()]).await {
+                Ok(_bundle_id) => return Ok((tx.signatures[0], true)),
+                Err(err) => println!("\nBundle send failed, falling back: {:?}", err),
+            }
+        }
+        if let Some(tpu_client) = &self.tpu_client {
+            match tpu_client.try_send_wire_transaction(wire_tx).await {
+                Ok(()) => return Ok((tx.signatures[0], false)),
+                Err(err) => println!("\nTPU send failed, falling back to RPC: {:?}", err),
+            }
+        }
+        let sig = self.rpc_pool.send_all(tx, config).await?;
+        Ok((sig, false))
+    }
+
+    // The first of `sigs` to reach the client's commitment on any endpoint, with the
+    // error it failed with
+    async fn find_landed(&self, sigs: &[Signature]) -> Option<(Signature, Option<TransactionError>)> {
+        match self.rpc_pool.find_landed(sigs, self.rpc_client.commitment()).await {
+            Ok(Some((endpoint, sig, err))) => {
+                self.metrics.record_first_confirmation(endpoint);
+                Some((sig, err))
+            }
+            Ok(None) => None,
+            // Handle confirmation errors
+            Err(err) => {
+                println!("\nError: {:?}", err);
+                None
+            }
+        }
+    }
 
-                // Handle submit errors
-                Err(err) => {
-                    println!("\nError {:?}", err);
+    // `tip_sig` is the tip transaction of the bundles accepted for the transaction, if any
+    async fn finish_landed(&self, sig: Signature, err: Option<TransactionError>, tx_fee: u64, tip_sig: Option<Signature>) -> ClientResult<Signature> {
+        // The tip is only paid if a bundle landed, rather than a fallback send
+        let tip_paid = match (tip_sig, &self.bundle_sender) {
+            (Some(tip_sig), Some(bundle_sender)) => {
+                match self.rpc_pool.find_landed(&[tip_sig], self.rpc_client.commitment()).await {
+                    Ok(Some(_)) => bundle_sender.tip_cost(),
+                    _ => 0,
                 }
             }
-            stdout.flush().ok();
+            _ => 0,
+        };
 
-            // Retry
-            stdout.flush().ok();
-            std::thread::sleep(Duration::from_millis(GATEWAY_DELAY));
-            attempts += 1;
-            if attempts > GATEWAY_RETRIES {
-                return Err(ClientError {
+        // Fees are charged whether or not the transaction succeeded
+        let paid = match self.get_transaction_fee(&sig).await {
+            Ok(fee) => fee + tip_paid,
+            Err(err) => {
+                println!("\nError fetching the fee paid by {}, using the estimate: {:?}", sig, err);
+                tx_fee + tip_paid
+            }
+        };
+        self.budget.record(paid);
+        match err {
+            None => {
+                println!("{} Success: Transaction landed! sig: {}", chrono::offset::Local::now(), sig);
+                self.metrics.tx_landed.fetch_add(1, Ordering::Relaxed);
+                Ok(sig)
+            }
+            Some(err) => {
+                println!("\n{} Failed: Transaction landed with error {:?} sig: {}", chrono::offset::Local::now(), err, sig);
+                self.metrics.tx_failed.fetch_add(1, Ordering::Relaxed);
+                Err(ClientError {
                     request: None,
-                    kind: ClientErrorKind::Custom("Max retries".into()),
-                });
+                    kind: ClientErrorKind::TransactionError(err),
+                })
             }
         }
     }
-}
\ No newline at end of file
+}
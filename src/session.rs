@@ -0,0 +1,145 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::{native_token::lamports_to_sol, signature::Signer};
+
+use crate::{Miner, WALLETS};
+
+// Running totals for a single `mine` invocation
+pub struct Session {
+    started_at: DateTime<Local>,
+    iterations: u64,
+    landed: u64,
+    land_time_millis: u128,
+    reward_rate_sum: f64,
+    reward_rate_count: u64,
+    start_rewards: Vec<(Pubkey, u64)>,
+    // Fees already recorded against the spend budget when the session started
+    start_spent: u64,
+}
+
+#[derive(Serialize)]
+pub struct SessionReport {
+    pub started_at: String,
+    pub ended_at: String,
+    pub duration_secs: u64,
+    pub iterations: u64,
+    pub wallets: Vec<WalletReport>,
+    pub total_ore_earned: f64,
+    pub sol_spent_on_fees: f64,
+    pub avg_land_time_secs: f64,
+    pub avg_reward_rate: f64,
+    // Confirmations each RPC endpoint reported before the others
+    pub first_confirmations: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+pub struct WalletReport {
+    pub wallet: String,
+    pub ore_earned: f64,
+}
+
+impl Session {
+    pub fn iterations(&self) -> u64 {
+        self.iterations
+    }
+
+    pub fn record_iteration(&mut self) {
+        self.iterations += 1;
+    }
+
+    pub fn record_land(&mut self, millis: u128) {
+        self.landed += 1;
+        self.land_time_millis += millis;
+    }
+
+    pub fn record_reward_rate(&mut self, reward_rate: f64) {
+        self.reward_rate_sum += reward_rate;
+        self.reward_rate_count += 1;
+    }
+}
+
+impl Miner {
+    pub async fn start_session(&self) -> Session {
+        let mut start_rewards = Vec::new();
+        for wallet in 1..WALLETS + 1 {
+            let pubkey = self.signer_by_number(wallet).pubkey();
+            let Ok(proof) = self.fetch_proof(pubkey).await else {
+                continue;
+            };
+            start_rewards.push((pubkey, proof.total_rewards));
+        }
+        Session {
+            started_at: Local::now(),
+            iterations: 0,
+            landed: 0,
+            land_time_millis: 0,
+            reward_rate_sum: 0.0,
+            reward_rate_count: 0,
+            start_rewards,
+            start_spent: self.budget.spent(),
+        }
+    }
+
+    // Prints the end-of-session report, and writes it as JSON if a path is given
+    pub async fn finish_session(&self, session: Session, report_path: Option<String>) {
+        let ended_at = Local::now();
+        let mut wallets = Vec::new();
+        for (pubkey, start_rewards) in session.start_rewards.iter() {
+            let earned = match self.fetch_proof(*pubkey).await {
+                Ok(proof) => proof.total_rewards.saturating_sub(*start_rewards),
+                Err(_) => 0,
+            };
+            wallets.push(WalletReport {
+                wallet: pubkey.to_string(),
+                ore_earned: (earned as f64) / 10f64.powf(ore::TOKEN_DECIMALS as f64),
+            });
+        }
+        let report = SessionReport {
+            started_at: session.started_at.to_rfc3339(),
+            ended_at: ended_at.to_rfc3339(),
+            duration_secs: (ended_at - session.started_at).num_seconds().max(0) as u64,
+            iterations: session.iterations,
+            total_ore_earned: wallets.iter().map(|wallet| wallet.ore_earned).sum(),
+            wallets,
+            sol_spent_on_fees: lamports_to_sol(self.budget.spent().saturating_sub(session.start_spent)),
+            avg_land_time_secs: if session.landed > 0 {
+                (session.land_time_millis as f64) / (session.landed as f64) / 1000.0
+            } else {
+                0.0
+            },
+            avg_reward_rate: if session.reward_rate_count > 0 {
+                session.reward_rate_sum / (session.reward_rate_count as f64)
+            } else {
+                0.0
+            },
+            first_confirmations: self.metrics.first_confirmations(),
+        };
+
+        println!("\nSession report");
+        println!("Duration: {:?}", Duration::from_secs(report.duration_secs));
+        println!("Iterations: {}", report.iterations);
+        for wallet in report.wallets.iter() {
+            println!("Wallet {} earned: {} ORE", wallet.wallet, wallet.ore_earned);
+        }
+        println!("Total earned: {} ORE", report.total_ore_earned);
+        println!("SOL spent on fees: {}", report.sol_spent_on_fees);
+        println!("Avg land time: {:.1} seconds", report.avg_land_time_secs);
+        println!("Avg reward rate: {} ORE", report.avg_reward_rate);
+        for (endpoint, count) in report.first_confirmations.iter() {
+            println!("First confirmations from {}: {}", endpoint, count);
+        }
+
+        if let Some(report_path) = report_path {
+            match serde_json::to_string_pretty(&report) {
+                Ok(json) => match std::fs::write(&report_path, json) {
+                    Ok(()) => println!("Wrote session report to {}", report_path),
+                    Err(err) => println!("Failed to write session report to {}: {:?}", report_path, err),
+                },
+                Err(err) => println!("Failed to serialize session report: {:?}", err),
+            }
+        }
+    }
+}
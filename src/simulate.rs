@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+
+use ore::{
+    state::{Bus, Proof, Treasury},
+    BUS_ADDRESSES, BUS_COUNT, EPOCH_DURATION,
+};
+use rand::Rng;
+use solana_program::{
+    instruction::Instruction,
+    keccak::{hashv, Hash as KeccakHash},
+    pubkey::Pubkey,
+};
+use solana_sdk::{clock::Clock, signature::Signature};
+
+use crate::budget::estimate_fee;
+
+// Mirrors of the on-chain program's epoch constants
+const ONE_ORE: u64 = 10u64.pow(ore::TOKEN_DECIMALS as u32);
+const TARGET_EPOCH_REWARDS: u64 = ONE_ORE;
+const MAX_EPOCH_REWARDS: u64 = ONE_ORE * 2;
+const BUS_EPOCH_REWARDS: u64 = MAX_EPOCH_REWARDS / BUS_COUNT as u64;
+const SMOOTHING_FACTOR: u64 = 2;
+const INITIAL_REWARD_RATE: u64 = 10u64.pow(3);
+
+// Ore instruction discriminators
+const IX_RESET: u8 = 0;
+const IX_REGISTER: u8 = 1;
+const IX_MINE: u8 = 2;
+const IX_CLAIM: u8 = 3;
+
+// Virtual seconds it takes for a transaction to land
+const LAND_SECONDS: i64 = 1;
+
+// Lamports each simulated wallet starts out with
+const INITIAL_LAMPORTS: u64 = 1_000_000_000;
+
+// An in-process stand-in for the ore program and the cluster it runs on. Time only moves
+// forward when the miner waits or lands a transaction, so epochs pass as fast as the
+// mine loop can run.
+pub struct SimulatedChain {
+    treasury: Treasury,
+    busses: Vec<Bus>,
+    proofs: HashMap<Pubkey, Proof>,
+    lamports: HashMap<Pubkey, u64>,
+    token_balances: HashMap<Pubkey, u64>,
+    now: i64,
+    slot: u64,
+    // Mines per second landed by other miners, draining the busses
+    competition: u64,
+}
+
+impl SimulatedChain {
+    pub fn new(competition: u64) -> Self {
+        let now = chrono::Utc::now().timestamp();
+        Self {
+            treasury: Treasury {
+                bump: 0,
+                admin: Pubkey::default(),
+                // Every hash is a valid solution, so hashing costs nothing
+                difficulty: KeccakHash::new_from_array([u8::MAX; 32]).into(),
+                last_reset_at: now,
+                reward_rate: INITIAL_REWARD_RATE,
+                total_claimed_rewards: 0,
+            },
+            busses: (0..BUS_COUNT)
+                .map(|id| Bus {
+                    id: id as u64,
+                    rewards: BUS_EPOCH_REWARDS,
+                })
+                .collect(),
+            proofs: HashMap::new(),
+            lamports: HashMap::new(),
+            token_balances: HashMap::new(),
+            now,
+            slot: 0,
+            competition,
+        }
+    }
+
+    pub fn treasury(&self) -> Treasury {
+        self.treasury
+    }
+
+    pub fn busses(&self) -> Vec<Bus> {
+        self.busses.clone()
+    }
+
+    pub fn proof(&self, authority: &Pubkey) -> Option<Proof> {
+        self.proofs.get(authority).copied()
+    }
+
+    pub fn clock(&self) -> Clock {
+        Clock {
+            slot: self.slot,
+            unix_timestamp: self.now,
+            ..Clock::default()
+        }
+    }
+
+    pub fn balance(&self, pubkey: &Pubkey) -> u64 {
+        *self.lamports.get(pubkey).unwrap_or(&INITIAL_LAMPORTS)
+    }
+
+    pub fn token_balance(&self, token_account: &Pubkey) -> u64 {
+        *self.token_balances.get(token_account).unwrap_or(&0)
+    }
+
+    // Moves the virtual clock forward, letting competing miners drain the busses meanwhile
+    pub fn advance(&mut self, seconds: i64) {
+        let mut rng = rand::thread_rng();
+        for _ in 0..seconds.max(0) {
+            self.now += 1;
+            self.slot += 2;
+            if self.now >= self.treasury.last_reset_at.saturating_add(EPOCH_DURATION) {
+                continue;
+            }
+            for _ in 0..self.competition {
+                let bus = &mut self.busses[rng.gen_range(0..BUS_COUNT)];
+                if bus.rewards >= self.treasury.reward_rate {
+                    bus.rewards -= self.treasury.reward_rate;
+                }
+            }
+        }
+    }
+
+    // Applies a transaction atomically. On failure, returns the index of the failing
+    // instruction and the reason.
+    pub fn process(&mut self, ixs: &[Instruction], signers: &[Pubkey]) -> Result<Signature, (usize, String)> {
+        let mut next = self.fork();
+        for (idx, ix) in ixs.iter().enumerate() {
+            if ix.program_id != ore::ID {
+                continue;
+            }
+            next.apply(ix).map_err(|err| (idx, err))?;
+        }
+        if let Some(payer) = signers.first() {
+            let fee = estimate_fee(ixs, signers.len());
+            let balance = next.balance(payer);
+            if balance < fee {
+                return Err((0, "Insufficient SOL balance".into()));
+            }
+            next.lamports.insert(*payer, balance - fee);
+        }
+        *self = next;
+        self.advance(LAND_SECONDS);
+        Ok(Signature::new_unique())
+    }
+
+    pub fn fork(&self) -> Self {
+        Self {
+            treasury: self.treasury,
+            busses: self.busses.clone(),
+            proofs: self.proofs.clone(),
+            lamports: self.lamports.clone(),
+            token_balances: self.token_balances.clone(),
+            now: self.now,
+            slot: self.slot,
+            competition: self.competition,
+        }
+    }
+
+    fn apply(&mut self, ix: &Instruction) -> Result<(), String> {
+        let signer = ix.accounts.first().map(|meta| meta.pubkey).unwrap_or_default();
+        match ix.data.first().copied() {
+            Some(IX_RESET) => self.reset(),
+            Some(IX_REGISTER) => {
+                if self.proofs.contains_key(&signer) {
+                    return Err("Proof already exists".into());
+                }
+                self.proofs.insert(
+                    signer,
+                    Proof {
+                        authority: signer,
+                        claimable_rewards: 0,
+                        hash: hashv(&[signer.as_ref()]).into(),
+                        total_hashes: 0,
+                        total_rewards: 0,
+                    },
+                );
+                Ok(())
+            }
+            Some(IX_MINE) => {
+                if ix.data.len() < 41 {
+                    return Err("Invalid mine args".into());
+                }
+                let hash = KeccakHash::new_from_array(ix.data[1..33].try_into().unwrap());
+                let nonce = u64::from_le_bytes(ix.data[33..41].try_into().unwrap());
+                let bus_address = ix.accounts.get(1).map(|meta| meta.pubkey).unwrap_or_default();
+                self.mine(signer, bus_address, hash, nonce)
+            }
+            Some(IX_CLAIM) => {
+                if ix.data.len() < 9 {
+                    return Err("Invalid claim args".into());
+                }
+                let amount = u64::from_le_bytes(ix.data[1..9].try_into().unwrap());
+                let beneficiary = ix.accounts.get(1).map(|meta| meta.pubkey).unwrap_or_default();
+                let proof = self.proofs.get_mut(&signer).ok_or("Proof not found")?;
+                if proof.claimable_rewards < amount {
+                    return Err("Claim amount exceeds claimable rewards".into());
+                }
+                proof.claimable_rewards -= amount;
+                self.treasury.total_claimed_rewards += amount;
+                *self.token_balances.entry(beneficiary).or_default() += amount;
+                Ok(())
+            }
+            _ => Err("Unsupported instruction".into()),
+        }
+    }
+
+    fn reset(&mut self) -> Result<(), String> {
+        if self.now < self.treasury.last_reset_at.saturating_add(EPOCH_DURATION) {
+            return Err("Epoch has not ended yet".into());
+        }
+        let remaining: u64 = self.busses.iter().map(|bus| bus.rewards).sum();
+        let epoch_rewards = MAX_EPOCH_REWARDS.saturating_sub(remaining);
+        self.treasury.reward_rate = next_reward_rate(self.treasury.reward_rate, epoch_rewards);
+        self.treasury.last_reset_at = self.now;
+        for bus in self.busses.iter_mut() {
+            bus.rewards = BUS_EPOCH_REWARDS;
+        }
+        Ok(())
+    }
+
+    fn mine(&mut self, signer: Pubkey, bus_address: Pubkey, hash: KeccakHash, nonce: u64) -> Result<(), String> {
+        if self.now >= self.treasury.last_reset_at.saturating_add(EPOCH_DURATION) {
+            return Err("Epoch needs reset".into());
+        }
+        let bus_id = BUS_ADDRESSES
+            .iter()
+            .position(|address| address.eq(&bus_address))
+            .ok_or("Invalid bus")?;
+        let reward_rate = self.treasury.reward_rate;
+        let difficulty: KeccakHash = self.treasury.difficulty.into();
+        let proof = self.proofs.get_mut(&signer).ok_or("Proof not found")?;
+        let expected = hashv(&[
+            KeccakHash::from(proof.hash).as_ref(),
+            signer.as_ref(),
+            nonce.to_le_bytes().as_slice(),
+        ]);
+        if expected != hash || hash > difficulty {
+            return Err("Invalid hash".into());
+        }
+        let bus = &mut self.busses[bus_id];
+        if bus.rewards < reward_rate {
+            return Err("Insufficient bus rewards".into());
+        }
+        bus.rewards -= reward_rate;
+        proof.claimable_rewards += reward_rate;
+        proof.total_rewards += reward_rate;
+        proof.total_hashes += 1;
+        proof.hash = hashv(&[hash.as_ref(), signer.as_ref(), self.slot.to_le_bytes().as_slice()]).into();
+        Ok(())
+    }
+}
+
+// Same adjustment the program makes at each reset, steering towards the target epoch rewards
+fn next_reward_rate(current_rate: u64, epoch_rewards: u64) -> u64 {
+    if epoch_rewards == 0 {
+        return current_rate;
+    }
+    let new_rate = (current_rate as u128)
+        .saturating_mul(TARGET_EPOCH_REWARDS as u128)
+        .saturating_div(epoch_rewards as u128) as u64;
+    new_rate
+        .min(current_rate.saturating_mul(SMOOTHING_FACTOR))
+        .max(current_rate.saturating_div(SMOOTHING_FACTOR))
+        .max(1)
+        .min(BUS_EPOCH_REWARDS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registered(signer: Pubkey) -> SimulatedChain {
+        let mut chain = SimulatedChain::new(0);
+        chain.process(&[ore::instruction::register(signer)], &[signer]).unwrap();
+        chain
+    }
+
+    fn mine_ix(chain: &SimulatedChain, signer: Pubkey, bus: usize, nonce: u64) -> Instruction {
+        let proof = chain.proof(&signer).unwrap();
+        let hash = hashv(&[
+            KeccakHash::from(proof.hash).as_ref(),
+            signer.as_ref(),
+            nonce.to_le_bytes().as_slice(),
+        ]);
+        ore::instruction::mine(signer, BUS_ADDRESSES[bus], hash.into(), nonce)
+    }
+
+    #[test]
+    fn mine_pays_the_reward_rate_from_the_bus() {
+        let signer = Pubkey::new_unique();
+        let mut chain = registered(signer);
+        let reward_rate = chain.treasury().reward_rate;
+        let hash_before = KeccakHash::from(chain.proof(&signer).unwrap().hash);
+
+        let ix = mine_ix(&chain, signer, 3, 7);
+        chain.process(&[ix], &[signer]).unwrap();
+
+        let proof = chain.proof(&signer).unwrap();
+        assert_eq!(proof.claimable_rewards, reward_rate);
+        assert_eq!(proof.total_hashes, 1);
+        assert_ne!(KeccakHash::from(proof.hash), hash_before);
+        assert_eq!(chain.busses()[3].rewards, BUS_EPOCH_REWARDS - reward_rate);
+    }
+
+    #[test]
+    fn mine_rejects_a_hash_for_another_nonce() {
+        let signer = Pubkey::new_unique();
+        let mut chain = registered(signer);
+        let mut ix = mine_ix(&chain, signer, 0, 7);
+        ix.data[33..41].copy_from_slice(&8u64.to_le_bytes());
+
+        let err = chain.process(&[ix], &[signer]).unwrap_err();
+        assert_eq!(err, (0, "Invalid hash".to_string()));
+        assert_eq!(chain.proof(&signer).unwrap().claimable_rewards, 0);
+    }
+
+    #[test]
+    fn failed_transactions_leave_the_chain_untouched() {
+        let signer = Pubkey::new_unique();
+        let mut chain = registered(signer);
+        let balance = chain.balance(&signer);
+        let now = chain.clock().unix_timestamp;
+        let ixs = [mine_ix(&chain, signer, 0, 7), ore::instruction::claim(signer, signer, u64::MAX)];
+
+        assert_eq!(chain.process(&ixs, &[signer]).unwrap_err().0, 1);
+        assert_eq!(chain.proof(&signer).unwrap().claimable_rewards, 0);
+        assert_eq!(chain.busses()[0].rewards, BUS_EPOCH_REWARDS);
+        assert_eq!(chain.balance(&signer), balance);
+        assert_eq!(chain.clock().unix_timestamp, now);
+    }
+
+    #[test]
+    fn mine_needs_a_reset_once_the_epoch_ends() {
+        let signer = Pubkey::new_unique();
+        let mut chain = registered(signer);
+        chain.advance(EPOCH_DURATION);
+
+        let ix = mine_ix(&chain, signer, 0, 7);
+        assert_eq!(chain.process(&[ix], &[signer]).unwrap_err(), (0, "Epoch needs reset".to_string()));
+    }
+
+    #[test]
+    fn reset_only_after_the_epoch_ends() {
+        let signer = Pubkey::new_unique();
+        let mut chain = registered(signer);
+        let ix = mine_ix(&chain, signer, 0, 7);
+        chain.process(&[ix], &[signer]).unwrap();
+
+        let reset = ore::instruction::reset(signer);
+        assert_eq!(
+            chain.process(&[reset.clone()], &[signer]).unwrap_err(),
+            (0, "Epoch has not ended yet".to_string())
+        );
+
+        chain.advance(EPOCH_DURATION);
+        chain.process(&[reset], &[signer]).unwrap();
+        let treasury = chain.treasury();
+        // The reset lands a second before the clock moves on
+        assert_eq!(treasury.last_reset_at, chain.clock().unix_timestamp - LAND_SECONDS);
+        assert!(chain.busses().iter().all(|bus| bus.rewards == BUS_EPOCH_REWARDS));
+        // Far fewer rewards than the target were mined, so the rate doubles
+        assert_eq!(treasury.reward_rate, INITIAL_REWARD_RATE * SMOOTHING_FACTOR);
+    }
+
+    #[test]
+    fn reward_rate_steers_towards_target_within_smoothing() {
+        assert_eq!(next_reward_rate(1000, 0), 1000);
+        assert_eq!(next_reward_rate(1000, TARGET_EPOCH_REWARDS), 1000);
+        assert_eq!(next_reward_rate(1000, TARGET_EPOCH_REWARDS * 4 / 3), 750);
+        assert_eq!(next_reward_rate(1000, TARGET_EPOCH_REWARDS * 4), 500);
+        assert_eq!(next_reward_rate(1000, TARGET_EPOCH_REWARDS / 4), 2000);
+    }
+
+    #[test]
+    fn claim_moves_rewards_to_the_beneficiary() {
+        let signer = Pubkey::new_unique();
+        let beneficiary = Pubkey::new_unique();
+        let mut chain = registered(signer);
+        let ix = mine_ix(&chain, signer, 0, 7);
+        chain.process(&[ix], &[signer]).unwrap();
+        let reward_rate = chain.treasury().reward_rate;
+
+        chain
+            .process(&[ore::instruction::claim(signer, beneficiary, reward_rate - 1)], &[signer])
+            .unwrap();
+        assert_eq!(chain.token_balance(&beneficiary), reward_rate - 1);
+        assert_eq!(chain.proof(&signer).unwrap().claimable_rewards, 1);
+        assert_eq!(chain.treasury().total_claimed_rewards, reward_rate - 1);
+
+        let err = chain
+            .process(&[ore::instruction::claim(signer, beneficiary, 2)], &[signer])
+            .unwrap_err();
+        assert_eq!(err, (0, "Claim amount exceeds claimable rewards".to_string()));
+    }
+
+    #[test]
+    fn payer_is_charged_the_fee() {
+        let signer = Pubkey::new_unique();
+        let mut chain = SimulatedChain::new(0);
+        let ixs = [ore::instruction::register(signer)];
+        chain.process(&ixs, &[signer]).unwrap();
+        assert_eq!(chain.balance(&signer), INITIAL_LAMPORTS - estimate_fee(&ixs, 1));
+    }
+}
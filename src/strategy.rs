@@ -0,0 +1,395 @@
+use std::{str::FromStr, time::Duration};
+
+use ore::state::Treasury;
+
+use crate::epoch::EpochTiming;
+
+// Defaults carried over from the original reward-rate heuristic
+const DEFAULT_THRESHOLD_RATIO: f64 = 0.875;
+const DEFAULT_THRESHOLD_RETRIES: u64 = 3;
+const DEFAULT_EWMA_ALPHA: f64 = 0.2;
+const DEFAULT_EPOCH_START_WINDOW: i64 = 10;
+const RETRY_DELAY: Duration = Duration::from_millis(3000);
+
+// The subset of on-chain state a strategy looks at when deciding to submit
+#[derive(Clone, Copy, Debug)]
+pub struct TreasurySnapshot {
+    pub reward_rate: f64,
+    pub last_reset_at: i64,
+    pub now: i64,
+}
+
+impl TreasurySnapshot {
+    pub fn new(treasury: &Treasury, now: i64) -> Self {
+        Self {
+            reward_rate: (treasury.reward_rate as f64) / 10f64.powf(ore::TOKEN_DECIMALS as f64),
+            last_reset_at: treasury.last_reset_at,
+            now,
+        }
+    }
+
+    pub fn epoch(&self) -> EpochTiming {
+        EpochTiming::from_parts(self.last_reset_at, self.now)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Submit,
+    Wait { delay: Duration, reason: String },
+}
+
+pub trait SubmissionStrategy: Send {
+    // Decide whether solutions should be submitted now or held back
+    fn decide(&mut self, snapshot: &TreasurySnapshot) -> Decision;
+
+    // A line describing the strategy's internal state, for the mine display
+    fn summary(&self) -> Option<String> {
+        None
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum StrategyKind {
+    Always,
+    AverageThreshold { ratio: f64, retries: u64 },
+    Ewma { alpha: f64, ratio: f64, retries: u64 },
+    EpochStart { window: i64 },
+}
+
+impl StrategyKind {
+    pub fn build(&self) -> Box<dyn SubmissionStrategy> {
+        match *self {
+            StrategyKind::Always => Box::new(Always),
+            StrategyKind::AverageThreshold { ratio, retries } => {
+                Box::new(AverageThreshold::new(ratio, retries))
+            }
+            StrategyKind::Ewma { alpha, ratio, retries } => Box::new(Ewma::new(alpha, ratio, retries)),
+            StrategyKind::EpochStart { window } => Box::new(EpochStart::new(window)),
+        }
+    }
+}
+
+// Parses `always`, `average-threshold[:RATIO[:RETRIES]]`, `ewma[:ALPHA[:RATIO[:RETRIES]]]`
+// and `epoch-start[:WINDOW_SECONDS]`
+impl FromStr for StrategyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default();
+        let params: Vec<&str> = parts.collect();
+        let param = |i: usize| params.get(i).copied();
+        let kind = match name {
+            "always" => StrategyKind::Always,
+            "average-threshold" => StrategyKind::AverageThreshold {
+                ratio: parse_param(param(0), DEFAULT_THRESHOLD_RATIO)?,
+                retries: parse_param(param(1), DEFAULT_THRESHOLD_RETRIES)?,
+            },
+            "ewma" => StrategyKind::Ewma {
+                alpha: parse_param(param(0), DEFAULT_EWMA_ALPHA)?,
+                ratio: parse_param(param(1), DEFAULT_THRESHOLD_RATIO)?,
+                retries: parse_param(param(2), DEFAULT_THRESHOLD_RETRIES)?,
+            },
+            "epoch-start" => StrategyKind::EpochStart {
+                window: parse_param(param(0), DEFAULT_EPOCH_START_WINDOW)?,
+            },
+            _ => return Err(format!("unknown submission strategy `{}`", name)),
+        };
+        Ok(kind)
+    }
+}
+
+fn parse_param<T: FromStr>(param: Option<&str>, default: T) -> Result<T, String> {
+    match param {
+        Some(param) => param
+            .parse()
+            .map_err(|_| format!("invalid strategy parameter `{}`", param)),
+        None => Ok(default),
+    }
+}
+
+// Submits every solution as soon as it is found
+pub struct Always;
+
+impl SubmissionStrategy for Always {
+    fn decide(&mut self, _snapshot: &TreasurySnapshot) -> Decision {
+        Decision::Submit
+    }
+}
+
+// Holds submissions while the reward rate is below `ratio` of the running average,
+// giving up and submitting anyway after `retries` waits
+pub struct AverageThreshold {
+    ratio: f64,
+    max_retries: u64,
+    retries: u64,
+    sum: f64,
+    count: u64,
+    last_rate: f64,
+}
+
+impl AverageThreshold {
+    pub fn new(ratio: f64, max_retries: u64) -> Self {
+        Self {
+            ratio,
+            max_retries,
+            retries: 0,
+            sum: 0.0,
+            count: 0,
+            last_rate: 0.0,
+        }
+    }
+
+    fn average(&self) -> Option<f64> {
+        if self.count > 0 {
+            Some(self.sum / self.count as f64)
+        } else {
+            None
+        }
+    }
+}
+
+impl SubmissionStrategy for AverageThreshold {
+    fn decide(&mut self, snapshot: &TreasurySnapshot) -> Decision {
+        // Don't count the same rate repeating
+        if self.last_rate != snapshot.reward_rate {
+            self.last_rate = snapshot.reward_rate;
+            self.sum += snapshot.reward_rate;
+            self.count += 1;
+        }
+        let average = self.average().unwrap_or(snapshot.reward_rate);
+        if snapshot.reward_rate < average * self.ratio && self.retries < self.max_retries {
+            self.retries += 1;
+            return Decision::Wait {
+                delay: RETRY_DELAY,
+                reason: "Current reward rate less than average, waiting a few more seconds...".into(),
+            };
+        }
+        self.retries = 0;
+        Decision::Submit
+    }
+
+    fn summary(&self) -> Option<String> {
+        self.average().map(|average| format!("Avg reward rate: {} ORE", average))
+    }
+}
+
+// Like `AverageThreshold`, but compares against an exponentially weighted moving average
+// so that recent epochs count more than old ones
+pub struct Ewma {
+    alpha: f64,
+    ratio: f64,
+    max_retries: u64,
+    retries: u64,
+    ewma: Option<f64>,
+    last_rate: f64,
+}
+
+impl Ewma {
+    pub fn new(alpha: f64, ratio: f64, max_retries: u64) -> Self {
+        Self {
+            alpha,
+            ratio,
+            max_retries,
+            retries: 0,
+            ewma: None,
+            last_rate: 0.0,
+        }
+    }
+}
+
+impl SubmissionStrategy for Ewma {
+    fn decide(&mut self, snapshot: &TreasurySnapshot) -> Decision {
+        if self.last_rate != snapshot.reward_rate {
+            self.last_rate = snapshot.reward_rate;
+            self.ewma = Some(match self.ewma {
+                Some(ewma) => self.alpha * snapshot.reward_rate + (1.0 - self.alpha) * ewma,
+                None => snapshot.reward_rate,
+            });
+        }
+        let ewma = self.ewma.unwrap_or(snapshot.reward_rate);
+        if snapshot.reward_rate < ewma * self.ratio && self.retries < self.max_retries {
+            self.retries += 1;
+            return Decision::Wait {
+                delay: RETRY_DELAY,
+                reason: "Current reward rate below moving average, waiting a few more seconds...".into(),
+            };
+        }
+        self.retries = 0;
+        Decision::Submit
+    }
+
+    fn summary(&self) -> Option<String> {
+        self.ewma.map(|ewma| format!("EWMA reward rate: {} ORE", ewma))
+    }
+}
+
+// Holds solutions until just after the next epoch reset, when busses are full
+pub struct EpochStart {
+    window: i64,
+}
+
+impl EpochStart {
+    pub fn new(window: i64) -> Self {
+        Self { window }
+    }
+}
+
+impl SubmissionStrategy for EpochStart {
+    fn decide(&mut self, snapshot: &TreasurySnapshot) -> Decision {
+        let epoch = snapshot.epoch();
+
+        // Submit only once the epoch has actually been reset, the busses stay drained
+        // until someone does
+        if epoch.is_fresh(self.window) {
+            return Decision::Submit;
+        }
+        if epoch.needs_reset() {
+            return Decision::Wait {
+                delay: RETRY_DELAY,
+                reason: "Waiting for the epoch to be reset...".into(),
+            };
+        }
+        let remaining = epoch.seconds_to_reset();
+        Decision::Wait {
+            delay: Duration::from_secs(remaining as u64),
+            reason: format!("Waiting {} seconds for the next epoch to start...", remaining),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ore::EPOCH_DURATION;
+
+    fn snapshot(reward_rate: f64) -> TreasurySnapshot {
+        TreasurySnapshot {
+            reward_rate,
+            last_reset_at: 0,
+            now: 30,
+        }
+    }
+
+    fn epoch_snapshot(last_reset_at: i64, now: i64) -> TreasurySnapshot {
+        TreasurySnapshot {
+            reward_rate: 1.0,
+            last_reset_at,
+            now,
+        }
+    }
+
+    fn is_wait(decision: &Decision) -> bool {
+        matches!(decision, Decision::Wait { .. })
+    }
+
+    #[test]
+    fn average_threshold_waits_then_submits_after_retries() {
+        let mut strategy = AverageThreshold::new(0.875, 2);
+        assert_eq!(strategy.decide(&snapshot(1.0)), Decision::Submit);
+        assert_eq!(strategy.decide(&snapshot(2.0)), Decision::Submit);
+
+        // 0.5 stays well under the threshold even once it is averaged in
+        assert!(is_wait(&strategy.decide(&snapshot(0.5))));
+        assert!(is_wait(&strategy.decide(&snapshot(0.5))));
+        assert_eq!(strategy.decide(&snapshot(0.5)), Decision::Submit);
+
+        // Retries start over after a submission
+        assert!(is_wait(&strategy.decide(&snapshot(0.5))));
+    }
+
+    #[test]
+    fn average_threshold_ignores_repeated_rates() {
+        let mut strategy = AverageThreshold::new(0.875, 3);
+        strategy.decide(&snapshot(1.0));
+        strategy.decide(&snapshot(1.0));
+        strategy.decide(&snapshot(1.0));
+        strategy.decide(&snapshot(3.0));
+        assert_eq!(strategy.average(), Some(2.0));
+    }
+
+    #[test]
+    fn ewma_updates_on_new_rates() {
+        let mut strategy = Ewma::new(0.5, 0.875, 3);
+        strategy.decide(&snapshot(1.0));
+        assert_eq!(strategy.ewma, Some(1.0));
+        strategy.decide(&snapshot(3.0));
+        assert_eq!(strategy.ewma, Some(2.0));
+
+        // Repeats don't move the average
+        strategy.decide(&snapshot(3.0));
+        assert_eq!(strategy.ewma, Some(2.0));
+
+        // 1.0 is under 0.875 of the average once it is folded in (1.5)
+        assert!(is_wait(&strategy.decide(&snapshot(1.0))));
+        assert_eq!(strategy.ewma, Some(1.5));
+    }
+
+    #[test]
+    fn epoch_start_submits_inside_window() {
+        let mut strategy = EpochStart::new(10);
+        assert_eq!(strategy.decide(&epoch_snapshot(100, 100)), Decision::Submit);
+        assert_eq!(strategy.decide(&epoch_snapshot(100, 109)), Decision::Submit);
+    }
+
+    #[test]
+    fn epoch_start_waits_for_next_epoch_outside_window() {
+        let mut strategy = EpochStart::new(10);
+        match strategy.decide(&epoch_snapshot(100, 110)) {
+            Decision::Wait { delay, .. } => {
+                assert_eq!(delay, Duration::from_secs((EPOCH_DURATION - 10) as u64))
+            }
+            Decision::Submit => panic!("submitted outside the window"),
+        }
+    }
+
+    #[test]
+    fn epoch_start_waits_for_reset_when_due() {
+        let mut strategy = EpochStart::new(10);
+        let due = epoch_snapshot(100, 100 + EPOCH_DURATION);
+        assert_eq!(
+            strategy.decide(&due),
+            Decision::Wait {
+                delay: RETRY_DELAY,
+                reason: "Waiting for the epoch to be reset...".into(),
+            }
+        );
+
+        // Submits once someone has reset it
+        let reset = epoch_snapshot(100 + EPOCH_DURATION, 100 + EPOCH_DURATION + 1);
+        assert_eq!(strategy.decide(&reset), Decision::Submit);
+    }
+
+    #[test]
+    fn parses_strategy_kinds() {
+        assert!(matches!(StrategyKind::from_str("always"), Ok(StrategyKind::Always)));
+        assert!(matches!(
+            StrategyKind::from_str("average-threshold"),
+            Ok(StrategyKind::AverageThreshold { ratio, retries }) if ratio == DEFAULT_THRESHOLD_RATIO && retries == DEFAULT_THRESHOLD_RETRIES
+        ));
+        assert!(matches!(
+            StrategyKind::from_str("average-threshold:0.5:7"),
+            Ok(StrategyKind::AverageThreshold { ratio, retries }) if ratio == 0.5 && retries == 7
+        ));
+        assert!(matches!(
+            StrategyKind::from_str("ewma:0.3"),
+            Ok(StrategyKind::Ewma { alpha, ratio, retries })
+                if alpha == 0.3 && ratio == DEFAULT_THRESHOLD_RATIO && retries == DEFAULT_THRESHOLD_RETRIES
+        ));
+        assert!(matches!(
+            StrategyKind::from_str("epoch-start:20"),
+            Ok(StrategyKind::EpochStart { window: 20 })
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_strategy_kinds() {
+        assert!(StrategyKind::from_str("").is_err());
+        assert!(StrategyKind::from_str("sometimes").is_err());
+        assert!(StrategyKind::from_str("average-threshold:high").is_err());
+        assert!(StrategyKind::from_str("ewma:0.2:0.9:lots").is_err());
+        assert!(StrategyKind::from_str("epoch-start:1.5").is_err());
+    }
+}
@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use solana_client::{
+    nonblocking::{rpc_client::RpcClient, tpu_client::TpuClient},
+    tpu_client::TpuClientConfig,
+};
+use solana_quic_client::{QuicConfig, QuicConnectionManager, QuicPool};
+
+pub type QuicTpuClient = TpuClient<QuicPool, QuicConnectionManager, QuicConfig>;
+
+// Connects to the leader schedule over RPC and the slot updates over websocket, so
+// transactions can go over QUIC straight to the current and upcoming leaders.
+// Returns None if that isn't possible, leaving sends on RPC.
+pub async fn connect(rpc_client: Arc<RpcClient>, websocket_url: &str) -> Option<QuicTpuClient> {
+    match TpuClient::new("ore-tpu", rpc_client, websocket_url, TpuClientConfig::default()).await {
+        Ok(tpu_client) => {
+            println!("Sending transactions to leaders over QUIC");
+            Some(tpu_client)
+        }
+        Err(err) => {
+            println!("Failed to connect to the TPU, sending over RPC instead: {:?}", err);
+            None
+        }
+    }
+}
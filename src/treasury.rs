@@ -1,14 +1,13 @@
-use crate::{
-    utils::{get_treasury, treasury_tokens_pubkey},
-    Miner,
-};
+use crate::{utils::treasury_tokens_pubkey, Miner};
 
 impl Miner {
     pub async fn treasury(&self) {
-        let client = self.rpc_client.clone();
-        if let Ok(Some(treasury_tokens)) = client.get_token_account(&treasury_tokens_pubkey()).await
-        {
-            let treasury = get_treasury(&self.rpc_client).await;
+        let treasury_tokens = self
+            .rpc_pool
+            .read(|client| async move { client.get_token_account(&treasury_tokens_pubkey()).await })
+            .await;
+        if let Ok(Some(treasury_tokens)) = treasury_tokens {
+            let treasury = self.fetch_treasury().await;
             let balance = treasury_tokens.token_amount.ui_amount_string;
             println!("{:} ORE", balance);
             println!("Admin: {}", treasury.admin);
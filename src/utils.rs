@@ -5,34 +5,39 @@ use ore::{
     utils::AccountDeserialize,
     MINT_ADDRESS, PROOF, TREASURY_ADDRESS,
 };
-use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    nonblocking::rpc_client::RpcClient,
+};
 use solana_program::{pubkey::Pubkey, sysvar};
 use solana_sdk::clock::Clock;
 use spl_associated_token_account::get_associated_token_address;
 
-pub async fn get_treasury(client: &RpcClient) -> Treasury {
-    let data = client
-        .get_account_data(&TREASURY_ADDRESS)
-        .await
-        .expect("Failed to get treasury account");
-    *Treasury::try_from_bytes(&data).expect("Failed to parse treasury account")
+pub async fn get_treasury(client: &RpcClient) -> ClientResult<Treasury> {
+    let data = client.get_account_data(&TREASURY_ADDRESS).await?;
+    Treasury::try_from_bytes(&data)
+        .copied()
+        .map_err(|_| parse_error("Failed to parse treasury account"))
 }
 
-pub async fn get_proof(client: &RpcClient, authority: Pubkey) -> Proof {
+pub async fn get_proof(client: &RpcClient, authority: Pubkey) -> ClientResult<Proof> {
     let proof_address = proof_pubkey(authority);
-    let data = client
-        .get_account_data(&proof_address)
-        .await
-        .expect("Failed to get miner account");
-    *Proof::try_from_bytes(&data).expect("Failed to parse miner account")
+    let data = client.get_account_data(&proof_address).await?;
+    Proof::try_from_bytes(&data)
+        .copied()
+        .map_err(|_| parse_error("Failed to parse miner account"))
+}
+
+pub async fn get_clock_account(client: &RpcClient) -> ClientResult<Clock> {
+    let data = client.get_account_data(&sysvar::clock::ID).await?;
+    bincode::deserialize::<Clock>(&data).map_err(|_| parse_error("Failed to deserialize clock"))
 }
 
-pub async fn get_clock_account(client: &RpcClient) -> Clock {
-    let data = client
-        .get_account_data(&sysvar::clock::ID)
-        .await
-        .expect("Failed to get miner account");
-    bincode::deserialize::<Clock>(&data).expect("Failed to deserialize clock")
+fn parse_error(msg: &str) -> ClientError {
+    ClientError {
+        request: None,
+        kind: ClientErrorKind::Custom(msg.into()),
+    }
 }
 
 #[cached]